@@ -1 +1,11 @@
+#[cfg(feature = "modbus")]
 pub mod modbus;
+
+use crate::module::registry::Registry;
+
+// Makes the drivers built in available to devices.
+#[cfg_attr(not(feature = "modbus"), allow(unused_variables))]
+pub fn register(registry: &mut Registry) {
+    #[cfg(feature = "modbus")]
+    modbus::register(registry);
+}
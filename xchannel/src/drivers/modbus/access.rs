@@ -0,0 +1,465 @@
+use std::collections::BTreeMap;
+use std::io::Error;
+
+use chrono::Utc;
+use futures::future::{join, join_all};
+
+use crate::error::*;
+use crate::module::driver::Tag;
+use crate::module::tag::{QualityReason, TagValue};
+use crate::module::value::Value;
+
+use super::client::{AsyncModbus, Client};
+use super::planner::{Planner, ReadBatch};
+use super::protocol::{DeviceIdObject, Exception, ExceptionResponse, ReadDeviceIdCode};
+use super::{Address, Area, Service};
+
+fn io_error(err: Error) -> XError {
+    XError::new(XErrorKind::DeviceError, &err.to_string())
+}
+
+fn is_illegal_function(err: &Error) -> bool {
+    err.get_ref()
+        .and_then(|err| err.downcast_ref::<ExceptionResponse>())
+        .is_some_and(|rsp| rsp.exception == Exception::IllegalFunction)
+}
+
+// Why a failed request left its tags without a value.
+fn failure_reason(err: &Error) -> QualityReason {
+    match err.get_ref() {
+        Some(err) if err.is::<ExceptionResponse>() => QualityReason::DeviceException,
+        _ => QualityReason::CommFailure,
+    }
+}
+
+async fn read_batch(
+    client: &dyn Client,
+    batch: &ReadBatch,
+    tags: &[Tag],
+) -> Vec<(usize, TagValue)> {
+    let (slave, address, quantity) = (batch.slave, batch.address, batch.quantity);
+    let values = match batch.area {
+        Area::Coil => client
+            .read_coils(slave, address, quantity)
+            .await
+            .map(|bits| batch.decode_bits(&bits)),
+        Area::DiscreteInput => client
+            .read_discrete_inputs(slave, address, quantity)
+            .await
+            .map(|bits| batch.decode_bits(&bits)),
+        Area::InputRegister => client
+            .read_input_registers(slave, address, quantity)
+            .await
+            .map(|regs| batch.decode_registers(&regs)),
+        Area::HoldingRegister => client
+            .read_hold_registers(slave, address, quantity)
+            .await
+            .map(|regs| batch.decode_registers(&regs)),
+    };
+
+    // values are stamped when the response arrives
+    let source_time = Utc::now();
+
+    match values {
+        Ok(values) => values
+            .into_iter()
+            .map(|(index, value)| {
+                let value = match value {
+                    Ok(value) => TagValue::good(value, source_time),
+                    Err(_) => bad(&tags[index], QualityReason::ConfigError),
+                };
+                (index, value)
+            })
+            .collect(),
+        Err(err) => {
+            let reason = failure_reason(&err);
+            batch
+                .slots
+                .iter()
+                .map(|slot| (slot.index, bad(&tags[slot.index], reason)))
+                .collect()
+        }
+    }
+}
+
+// Identification objects of one slave: a single object is read on its own,
+// several with one stream read of the category that holds them all.
+async fn read_objects(
+    client: &dyn Client,
+    slave: u8,
+    objects: &[u8],
+) -> Result<Vec<DeviceIdObject>, Error> {
+    let (read_code, object_id) = match objects {
+        [object] => (ReadDeviceIdCode::Specific, *object),
+        _ => match objects.iter().max() {
+            Some(0x00..=0x02) => (ReadDeviceIdCode::Basic, 0),
+            Some(0x03..=0x7F) => (ReadDeviceIdCode::Regular, 0),
+            _ => (ReadDeviceIdCode::Extended, 0),
+        },
+    };
+
+    let identification = client
+        .read_device_identification(slave, read_code, object_id)
+        .await?;
+    Ok(identification.objects)
+}
+
+async fn read_identification(
+    client: &dyn Client,
+    slave: u8,
+    slots: &[(usize, u8)],
+    tags: &[Tag],
+) -> Vec<(usize, TagValue)> {
+    let mut objects: Vec<u8> = slots.iter().map(|(_, object)| *object).collect();
+    objects.sort_unstable();
+    objects.dedup();
+
+    let result = read_objects(client, slave, &objects).await;
+    let source_time = Utc::now();
+
+    slots
+        .iter()
+        .map(|(index, object)| {
+            let value = match &result {
+                Ok(found) => match found.iter().find(|found| found.id == *object) {
+                    Some(found) => TagValue::good(
+                        Value::STRING {
+                            length: Some(found.value.len() as u16),
+                            str: Some(String::from_utf8_lossy(&found.value).into_owned()),
+                        },
+                        source_time,
+                    ),
+                    // the slave does not have the object
+                    None => bad(&tags[*index], QualityReason::ConfigError),
+                },
+                Err(err) => bad(&tags[*index], failure_reason(err)),
+            };
+            (*index, value)
+        })
+        .collect()
+}
+
+async fn read_diagnostic(
+    client: &dyn Client,
+    slave: u8,
+    sub_function: u16,
+    index: usize,
+    tags: &[Tag],
+) -> (usize, TagValue) {
+    let value = match client.diagnostics(slave, sub_function, 0).await {
+        Ok(counter) => TagValue::good(Value::UINT16(counter), Utc::now()),
+        Err(err) => bad(&tags[index], failure_reason(&err)),
+    };
+    (index, value)
+}
+
+// Reads the tags answered by a function of the slave, one request per slave
+// for identification objects and one per diagnostic counter.
+async fn read_services(client: &dyn Client, tags: &[Tag]) -> Vec<(usize, TagValue)> {
+    let mut objects: BTreeMap<u8, Vec<(usize, u8)>> = BTreeMap::new();
+    let mut counters = Vec::new();
+
+    for (index, tag) in tags.iter().enumerate() {
+        match Service::parse(tag) {
+            Some(Ok(Service::Identification { slave, object })) => {
+                objects.entry(slave).or_default().push((index, object))
+            }
+            Some(Ok(Service::Diagnostic {
+                slave,
+                sub_function,
+            })) => counters.push(read_diagnostic(client, slave, sub_function, index, tags)),
+            _ => {}
+        }
+    }
+
+    let (identification, counters) = join(
+        join_all(
+            objects
+                .iter()
+                .map(|(slave, slots)| read_identification(client, *slave, slots, tags)),
+        ),
+        join_all(counters),
+    )
+    .await;
+
+    identification
+        .into_iter()
+        .flatten()
+        .chain(counters)
+        .collect()
+}
+
+pub fn bad(tag: &Tag, reason: QualityReason) -> TagValue {
+    TagValue::bad(tag.value.clone(), reason)
+}
+
+// Reads the tags in as few requests as the planner manages; the batches are
+// issued together so a pipelining transport overlaps them. Returns one result
+// per tag, in order.
+pub async fn read_tags(client: &dyn Client, gap: u16, tags: &[Tag]) -> Vec<TagValue> {
+    // tags the planner rejects keep the configuration error, service tags
+    // are read on their own
+    let (batches, _) = Planner::new(gap).plan(tags);
+    let mut values: Vec<TagValue> = tags
+        .iter()
+        .map(|tag| bad(tag, QualityReason::ConfigError))
+        .collect();
+
+    let (batches, services) = join(
+        join_all(batches.iter().map(|batch| read_batch(client, batch, tags))),
+        read_services(client, tags),
+    )
+    .await;
+    for (index, value) in batches.into_iter().flatten().chain(services) {
+        values[index] = value;
+    }
+
+    values
+}
+
+// Writes a tag value, picking the function code from the area and the part of
+// the register the tag occupies.
+pub async fn write_tag(client: &dyn Client, tag: &Tag, value: &Value) -> XResult<()> {
+    if value.v_type() != tag.value.v_type() {
+        return Err(XError::new(XErrorKind::TagError, "Tag value type mismatch"));
+    }
+
+    if Service::parse(tag).is_some() {
+        return Err(XError::new(
+            XErrorKind::TagError,
+            &format!("{} is read-only", tag.name),
+        ));
+    }
+
+    let address = Address::try_from(tag)?;
+    match address.area {
+        Area::Coil => {
+            let state = match value {
+                Value::BIT(v) => *v != 0,
+                Value::BOOL(v) => *v,
+                _ => return Err(XError::new(XErrorKind::TagError, "invalid value for coil")),
+            };
+            client
+                .write_single_coil(address.slave, address.address, state)
+                .await
+                .map_err(io_error)
+        }
+        Area::DiscreteInput | Area::InputRegister => Err(XError::new(
+            XErrorKind::TagError,
+            &format!("{} is read-only", tag.name),
+        )),
+        Area::HoldingRegister => match value {
+            Value::BIT(_) | Value::BOOL(_) | Value::UINT8(_) | Value::INT8(_) => {
+                write_masked(client, &address, value).await
+            }
+            _ => {
+                let regs = address.encode_registers(value)?;
+                if let [reg] = regs[..] {
+                    client
+                        .write_single_register(address.slave, address.address, reg)
+                        .await
+                } else {
+                    client
+                        .write_multiple_registers(address.slave, address.address, &regs)
+                        .await
+                }
+                .map_err(io_error)
+            }
+        },
+    }
+}
+
+// Bits and bytes inside a holding register go through Mask Write Register;
+// servers without it get a read-modify-write instead, which is not atomic.
+async fn write_masked(client: &dyn Client, address: &Address, value: &Value) -> XResult<()> {
+    let (and_mask, or_mask) = address.encode_mask(value)?;
+
+    match client
+        .mask_write_register(address.slave, address.address, and_mask, or_mask)
+        .await
+    {
+        Ok(()) => Ok(()),
+        Err(err) if is_illegal_function(&err) => {
+            let current = client
+                .read_hold_registers(address.slave, address.address, 1)
+                .await
+                .map_err(io_error)?[0];
+            client
+                .write_single_register(
+                    address.slave,
+                    address.address,
+                    (current & and_mask) | (or_mask & !and_mask),
+                )
+                .await
+                .map_err(io_error)
+        }
+        Err(err) => Err(io_error(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Error;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use crate::module::driver::Tag;
+    use crate::module::tag::{Quality, QualityReason};
+    use crate::module::value::{DataType, Value};
+
+    use super::super::client::Client;
+    use super::super::protocol::{
+        DeviceIdObject, DeviceIdentification, Exception, ReadDeviceIdCode, Request, Response,
+    };
+    use super::{read_tags, write_tag};
+
+    // Holding registers of one slave without Mask Write Register support.
+    struct Registers(Mutex<Vec<u16>>);
+
+    #[async_trait]
+    impl Client for Registers {
+        async fn call(&self, _slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+            let mut regs = self.0.lock().unwrap();
+            let rsp = match request {
+                Request::ReadHoldingRegisters(address, quantity) => {
+                    match regs.get(address as usize..(address + quantity) as usize) {
+                        Some(regs) => Response::ReadHoldingRegisters(regs.to_vec()),
+                        None => Response::ExceptionResponse(0x03, Exception::IllegalDataAddress),
+                    }
+                }
+                Request::WriteSingleRegister(address, value) => {
+                    regs[address as usize] = value;
+                    Response::WriteSingleRegister(address, value)
+                }
+                Request::WriteMultipleRegisters(address, values) => {
+                    regs[address as usize..address as usize + values.len()].copy_from_slice(values);
+                    Response::WriteMultipleRegisters(address, values.len() as u16)
+                }
+                // only Mask Write Register is left to reach the stub
+                _ => Response::ExceptionResponse(0x16, Exception::IllegalFunction),
+            };
+            Ok(rsp)
+        }
+    }
+
+    // Slave that only identifies itself and counts bus messages.
+    struct Identity;
+
+    #[async_trait]
+    impl Client for Identity {
+        async fn call(&self, _slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+            let object = |id: u8, value: &str| DeviceIdObject {
+                id,
+                value: value.as_bytes().to_vec(),
+            };
+            let identification = |objects| {
+                Response::ReadDeviceIdentification(DeviceIdentification {
+                    more_follows: false,
+                    next_object_id: 0,
+                    objects,
+                })
+            };
+
+            let rsp = match request {
+                Request::ReadDeviceIdentification(ReadDeviceIdCode::Basic, 0) => {
+                    identification(vec![
+                        object(0, "vendor"),
+                        object(1, "product"),
+                        object(2, "1.0"),
+                    ])
+                }
+                Request::ReadDeviceIdentification(ReadDeviceIdCode::Specific, 1) => {
+                    identification(vec![object(1, "product")])
+                }
+                Request::Diagnostics(0x0B, 0) => Response::Diagnostics(0x0B, 7),
+                _ => Response::ExceptionResponse(0x08, Exception::IllegalFunction),
+            };
+            Ok(rsp)
+        }
+    }
+
+    fn tag(dtype: DataType, address: &str) -> Tag {
+        Tag {
+            name: "test".to_string(),
+            value: dtype.default_value(),
+            dtype,
+            address: address.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_registers() {
+        let client = Registers(Mutex::new(vec![0x0001, 0x0304, 0x0102]));
+        let tags = [
+            tag(DataType::DWORD, "1.42.CDAB"),
+            tag(DataType::WORD, "1.41"),
+            tag(DataType::WORD, "1.45"),
+        ];
+
+        let values = read_tags(&client, 0, &tags).await;
+        assert_eq!(values[0].value, Value::UINT32(0x0102_0304));
+        assert_eq!(values[0].quality, Quality::Good);
+        assert_eq!(values[1].value, Value::UINT16(1));
+        assert_eq!(values[2].quality, Quality::Bad);
+        assert_eq!(values[2].reason, Some(QualityReason::DeviceException));
+    }
+
+    #[tokio::test]
+    async fn write_registers() {
+        let client = Registers(Mutex::new(vec![0x00F0, 0, 0]));
+
+        write_tag(&client, &tag(DataType::BOOL, "1.41.0"), &Value::BOOL(true))
+            .await
+            .unwrap();
+        write_tag(&client, &tag(DataType::BYTE, "1.41.H"), &Value::UINT8(0xAB))
+            .await
+            .unwrap();
+        write_tag(
+            &client,
+            &tag(DataType::DWORD, "1.42.CDAB"),
+            &Value::UINT32(0x0102_0304),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*client.0.lock().unwrap(), [0xABF1, 0x0304, 0x0102]);
+
+        assert!(
+            write_tag(&client, &tag(DataType::WORD, "1.31"), &Value::UINT16(1))
+                .await
+                .is_err()
+        );
+        assert!(
+            write_tag(&client, &tag(DataType::WORD, "1.41"), &Value::INT16(1))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn read_services() {
+        let string = |str: &str| Value::STRING {
+            length: Some(str.len() as u16),
+            str: Some(str.to_string()),
+        };
+        let tags = [
+            tag(DataType::STRING, "1.ID.0"),
+            tag(DataType::STRING, "1.ID.2"),
+            tag(DataType::STRING, "2.ID.1"),
+            tag(DataType::WORD, "1.DIAG.11"),
+            tag(DataType::WORD, "1.DIAG.12"),
+        ];
+
+        let values = read_tags(&Identity, 0, &tags).await;
+        assert_eq!(values[0].value, string("vendor"));
+        assert_eq!(values[1].value, string("1.0"));
+        assert_eq!(values[2].value, string("product"));
+        assert_eq!(values[3].value, Value::UINT16(7));
+        assert_eq!(values[4].quality, Quality::Bad);
+        assert_eq!(values[4].reason, Some(QualityReason::DeviceException));
+
+        assert!(write_tag(&Identity, &tags[0], &string("other"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::{sink::SinkExt as _, stream::StreamExt as _};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::Mutex;
+use tokio::time;
+use tokio_util::codec::Framed;
+
+use super::super::protocol::{
+    ascii::{ClientCodec, Header, RequestAdu},
+    Request, Response,
+};
+
+use super::{discard_input, Client};
+
+pub struct AsyncAsciiClient<T> {
+    framed: Mutex<Framed<T, ClientCodec>>,
+    timeout: Duration,
+}
+
+impl<T> AsyncAsciiClient<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(transport: T, timeout: Duration) -> Self {
+        AsyncAsciiClient {
+            framed: Mutex::new(Framed::new(transport, ClientCodec::default())),
+            timeout,
+        }
+    }
+}
+
+#[async_trait]
+impl<T> Client for AsyncAsciiClient<T>
+where
+    T: Send + AsyncRead + AsyncWrite + Unpin,
+{
+    async fn call(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        let req_adu = RequestAdu {
+            header: Header { slave_id },
+            request,
+        };
+
+        let mut framed = self.framed.lock().await;
+        discard_input(framed.get_mut()).await?;
+        framed.read_buffer_mut().clear();
+
+        framed.send(req_adu).await?;
+        let res_adu = time::timeout(self.timeout, framed.next())
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, "request timed out"))?
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "connection closed"))??;
+
+        if res_adu.header.slave_id != slave_id {
+            return Err(Error::new(ErrorKind::InvalidData, "unexpected slave id"));
+        }
+
+        Ok(res_adu.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio_serial::SerialStream;
+
+    use super::super::super::protocol::{Request, Response};
+    use super::super::Client;
+    use super::AsyncAsciiClient;
+
+    #[tokio::test]
+    async fn call_over_pty() {
+        let (master, mut slave) = SerialStream::pair().unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 17];
+            slave.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req, b":010600010003F5\r\n");
+
+            slave.write_all(b":010600010003F5\r\n").await.unwrap();
+        });
+
+        let client = AsyncAsciiClient::new(master, Duration::from_secs(1));
+        let rsp = client
+            .call(1, Request::WriteSingleRegister(1, 3))
+            .await
+            .unwrap();
+        assert!(matches!(rsp, Response::WriteSingleRegister(1, 3)));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn late_reply_discarded() {
+        let (master, mut slave) = SerialStream::pair().unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 17];
+            slave.read_exact(&mut req).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            slave.write_all(b":010600010003F5\r\n").await.unwrap();
+
+            slave.read_exact(&mut req).await.unwrap();
+            slave.write_all(b":010600010004F4\r\n").await.unwrap();
+        });
+
+        let client = AsyncAsciiClient::new(master, Duration::from_millis(50));
+        let rsp = client.call(1, Request::WriteSingleRegister(1, 3)).await;
+        assert_eq!(rsp.err().unwrap().kind(), std::io::ErrorKind::TimedOut);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        // the reply to the first request is not taken for the second one
+        let rsp = client
+            .call(1, Request::WriteSingleRegister(1, 4))
+            .await
+            .unwrap();
+        assert!(matches!(rsp, Response::WriteSingleRegister(1, 4)));
+
+        server.await.unwrap();
+    }
+}
@@ -1,25 +1,62 @@
 use std::io::{Error, ErrorKind};
+use std::time::Duration;
 
 use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt as _};
+use tokio::time;
 
-use super::protocol::{Request, Response};
+use super::protocol::{
+    DeviceIdentification, ExceptionResponse, ReadDeviceIdCode, Request, Response,
+};
 
-mod tcp;
+pub mod ascii;
+pub mod rtu;
+pub mod tcp;
+pub mod udp;
+
+// Drops whatever is already waiting on the line, such as the late reply to a
+// request that timed out, so it is not taken for the next response.
+async fn discard_input<T: AsyncRead + Unpin>(transport: &mut T) -> Result<(), Error> {
+    let mut scratch = [0u8; 256];
+
+    loop {
+        match time::timeout(Duration::ZERO, transport.read(&mut scratch)).await {
+            Ok(Ok(0)) => return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+            Ok(Ok(_)) => continue,
+            Ok(Err(err)) => return Err(err),
+            Err(_) => return Ok(()),
+        }
+    }
+}
 
 #[async_trait]
-pub trait Client: Send {
-    async fn call(&mut self, slave_id: u8, request: Request<'_>) -> Result<Response, Error>;
+pub trait Client: Send + Sync {
+    async fn call(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error>;
 }
 
 #[async_trait]
 pub trait AsyncModbus: Client {
+    // Like `call`, but an exception response comes back as an error whose
+    // inner error is the `ExceptionResponse`.
+    async fn request(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        match Self::call(self, slave_id, request).await? {
+            Response::ExceptionResponse(function, exception) => {
+                Err(Error::other(ExceptionResponse {
+                    function,
+                    exception,
+                }))
+            }
+            rsp => Ok(rsp),
+        }
+    }
+
     async fn read_coils(
-        &mut self,
+        &self,
         slave_id: u8,
         address: u16,
         quantity: u16,
     ) -> Result<Vec<bool>, Error> {
-        let rsp = Self::call(self, slave_id, Request::ReadCoils(address, quantity)).await?;
+        let rsp = Self::request(self, slave_id, Request::ReadCoils(address, quantity)).await?;
 
         if let Response::ReadCoils(mut coils) = rsp {
             debug_assert!(coils.len() >= quantity.into());
@@ -31,12 +68,12 @@ pub trait AsyncModbus: Client {
     }
 
     async fn read_discrete_inputs(
-        &mut self,
+        &self,
         slave_id: u8,
         address: u16,
         quantity: u16,
     ) -> Result<Vec<bool>, Error> {
-        let rsp = Self::call(
+        let rsp = Self::request(
             self,
             slave_id,
             Request::ReadDiscreteInputs(address, quantity),
@@ -52,12 +89,12 @@ pub trait AsyncModbus: Client {
     }
 
     async fn read_input_registers(
-        &mut self,
+        &self,
         slave_id: u8,
         address: u16,
         quantity: u16,
     ) -> Result<Vec<u16>, Error> {
-        let rsp = Self::call(
+        let rsp = Self::request(
             self,
             slave_id,
             Request::ReadInputRegisters(address, quantity),
@@ -75,12 +112,12 @@ pub trait AsyncModbus: Client {
     }
 
     async fn read_hold_registers(
-        &mut self,
+        &self,
         slave_id: u8,
         address: u16,
         quantity: u16,
     ) -> Result<Vec<u16>, Error> {
-        let rsp = Self::call(
+        let rsp = Self::request(
             self,
             slave_id,
             Request::ReadHoldingRegisters(address, quantity),
@@ -98,12 +135,12 @@ pub trait AsyncModbus: Client {
     }
 
     async fn write_single_coil(
-        &mut self,
+        &self,
         slave_id: u8,
         address: u16,
         value: bool,
     ) -> Result<(), Error> {
-        let rsp = Self::call(self, slave_id, Request::WriteSingleCoil(address, value)).await?;
+        let rsp = Self::request(self, slave_id, Request::WriteSingleCoil(address, value)).await?;
 
         if let Response::WriteSingleCoil(rsp_addr, rsp_coil) = rsp {
             if rsp_addr != address || rsp_coil != value {
@@ -116,12 +153,13 @@ pub trait AsyncModbus: Client {
     }
 
     async fn write_single_register(
-        &mut self,
+        &self,
         slave_id: u8,
         address: u16,
         data: u16,
     ) -> Result<(), Error> {
-        let rsp = Self::call(self, slave_id, Request::WriteSingleRegister(address, data)).await?;
+        let rsp =
+            Self::request(self, slave_id, Request::WriteSingleRegister(address, data)).await?;
 
         if let Response::WriteSingleRegister(rsp_addr, rsp_word) = rsp {
             if rsp_addr != address || rsp_word != data {
@@ -133,15 +171,20 @@ pub trait AsyncModbus: Client {
         }
     }
 
-    async fn write_multiple_coils(
-        &mut self,
+    async fn write_multiple_registers(
+        &self,
         slave_id: u8,
         address: u16,
-        data: &[bool],
+        data: &[u16],
     ) -> Result<(), Error> {
-        let rsp = Self::call(self, slave_id, Request::WriteMultipleCoils(address, data)).await?;
+        let rsp = Self::request(
+            self,
+            slave_id,
+            Request::WriteMultipleRegisters(address, data),
+        )
+        .await?;
 
-        if let Response::WriteMultipleCoils(rsp_addr, rsp_cnt) = rsp {
+        if let Response::WriteMultipleRegisters(rsp_addr, rsp_cnt) = rsp {
             if rsp_addr != address || data.len() != rsp_cnt as usize {
                 return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
             }
@@ -151,21 +194,22 @@ pub trait AsyncModbus: Client {
         }
     }
 
-    async fn write_multiple_registers(
-        &mut self,
+    async fn mask_write_register(
+        &self,
         slave_id: u8,
         address: u16,
-        data: &[u16],
+        and_mask: u16,
+        or_mask: u16,
     ) -> Result<(), Error> {
-        let rsp = Self::call(
+        let rsp = Self::request(
             self,
             slave_id,
-            Request::WriteMultipleRegisters(address, data),
+            Request::MaskWriteRegister(address, and_mask, or_mask),
         )
         .await?;
 
-        if let Response::WriteMultipleRegisters(rsp_addr, rsp_cnt) = rsp {
-            if rsp_addr != address || data.len() != rsp_cnt as usize {
+        if let Response::MaskWriteRegister(rsp_addr, rsp_and, rsp_or) = rsp {
+            if rsp_addr != address || rsp_and != and_mask || rsp_or != or_mask {
                 return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
             }
             Ok(())
@@ -173,4 +217,134 @@ pub trait AsyncModbus: Client {
             Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
         }
     }
+
+    // Objects that do not fit into one response are fetched with follow-up
+    // requests until the server reports no more follow.
+    async fn read_device_identification(
+        &self,
+        slave_id: u8,
+        read_code: ReadDeviceIdCode,
+        object_id: u8,
+    ) -> Result<DeviceIdentification, Error> {
+        let mut next_object_id = object_id;
+        let mut identification: Option<DeviceIdentification> = None;
+
+        loop {
+            let rsp = Self::request(
+                self,
+                slave_id,
+                Request::ReadDeviceIdentification(read_code, next_object_id),
+            )
+            .await?;
+
+            let part = match rsp {
+                Response::ReadDeviceIdentification(part) => part,
+                _ => return Err(Error::new(ErrorKind::InvalidData, "unexpected response")),
+            };
+            let more_follows = part.more_follows && read_code != ReadDeviceIdCode::Specific;
+            if more_follows && part.next_object_id <= next_object_id {
+                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+            }
+            next_object_id = part.next_object_id;
+
+            match identification.as_mut() {
+                Some(id) => id.objects.extend(part.objects),
+                None => identification = Some(part),
+            }
+            if !more_follows {
+                break;
+            }
+        }
+
+        let mut identification = identification.unwrap();
+        identification.more_follows = false;
+        identification.next_object_id = 0;
+        Ok(identification)
+    }
+
+    async fn diagnostics(&self, slave_id: u8, sub_function: u16, data: u16) -> Result<u16, Error> {
+        let rsp = Self::request(self, slave_id, Request::Diagnostics(sub_function, data)).await?;
+
+        if let Response::Diagnostics(rsp_sub, rsp) = rsp {
+            if rsp_sub != sub_function {
+                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+            }
+            Ok(rsp)
+        } else {
+            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+        }
+    }
+}
+
+impl<T: Client + ?Sized> AsyncModbus for T {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Error;
+
+    use async_trait::async_trait;
+
+    use super::super::protocol::{
+        DeviceIdObject, DeviceIdentification, Exception, ReadDeviceIdCode, Request, Response,
+    };
+    use super::{AsyncModbus, Client};
+
+    // Answers every request the way a well-behaved server would.
+    struct Server;
+
+    #[async_trait]
+    impl Client for Server {
+        async fn call(&self, _slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+            Ok(match request {
+                // one object per response
+                Request::ReadDeviceIdentification(_, id) => {
+                    Response::ReadDeviceIdentification(DeviceIdentification {
+                        more_follows: id < 2,
+                        next_object_id: id + 1,
+                        objects: vec![DeviceIdObject {
+                            id,
+                            value: vec![b'a' + id],
+                        }],
+                    })
+                }
+                Request::Diagnostics(0x0B, _) => Response::Diagnostics(0x0B, 42),
+                Request::Diagnostics(_, _) => {
+                    Response::ExceptionResponse(0x08, Exception::IllegalFunction)
+                }
+                _ => unreachable!(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn diagnostics() {
+        let client = Server;
+
+        assert_eq!(client.diagnostics(1, 0x0B, 0).await.unwrap(), 42);
+        assert!(client.diagnostics(1, 0x0C, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn device_identification_follows() {
+        let client = Server;
+
+        let id = client
+            .read_device_identification(1, ReadDeviceIdCode::Basic, 0)
+            .await
+            .unwrap();
+        assert!(!id.more_follows);
+        let objects: Vec<(u8, &[u8])> = id
+            .objects
+            .iter()
+            .map(|object| (object.id, object.value.as_slice()))
+            .collect();
+        assert_eq!(objects, [(0, &b"a"[..]), (1, b"b"), (2, b"c")]);
+
+        // a specific object is read alone
+        let id = client
+            .read_device_identification(1, ReadDeviceIdCode::Specific, 1)
+            .await
+            .unwrap();
+        assert_eq!(id.objects.len(), 1);
+    }
 }
@@ -0,0 +1,227 @@
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::sync::Mutex;
+use tokio::time::{self, Instant};
+use tokio_util::codec::{Decoder as _, Encoder as _};
+
+use super::super::protocol::{
+    rtu::{ClientCodec, Header, RequestAdu},
+    Request, Response,
+};
+
+use super::{discard_input, Client};
+
+// Above 19200 baud the spec fixes t3.5 instead of scaling it with the baud rate.
+const FIXED_SILENT_INTERVAL: Duration = Duration::from_micros(1750);
+// Serial device servers forward a frame in several segments, so the gap that
+// ends a frame has to allow for network jitter instead of line timing.
+const NETWORK_FRAME_GAP: Duration = Duration::from_millis(100);
+
+pub fn silent_interval(baud_rate: u32, bits_per_char: u32) -> Duration {
+    if baud_rate > 19200 {
+        FIXED_SILENT_INTERVAL
+    } else {
+        Duration::from_micros(3_500_000 * u64::from(bits_per_char) / u64::from(baud_rate.max(1)))
+    }
+}
+
+struct Line<T> {
+    transport: T,
+    codec: ClientCodec,
+    buffer: BytesMut,
+    last_activity: Instant,
+}
+
+// The bus is half duplex, so callers queue on the line and only one request
+// is ever outstanding.
+pub struct AsyncRtuClient<T> {
+    line: Mutex<Line<T>>,
+    silent_interval: Duration,
+    frame_gap: Duration,
+    timeout: Duration,
+}
+
+impl<T> AsyncRtuClient<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(transport: T, silent_interval: Duration, timeout: Duration) -> Self {
+        AsyncRtuClient {
+            line: Mutex::new(Line {
+                transport,
+                codec: ClientCodec::default(),
+                buffer: BytesMut::with_capacity(256),
+                last_activity: Instant::now(),
+            }),
+            silent_interval,
+            frame_gap: silent_interval,
+            timeout,
+        }
+    }
+
+    // RTU framing tunnelled through TCP: the server paces the serial line.
+    pub fn over_tcp(transport: T, timeout: Duration) -> Self {
+        AsyncRtuClient {
+            frame_gap: NETWORK_FRAME_GAP,
+            ..Self::new(transport, Duration::ZERO, timeout)
+        }
+    }
+}
+
+impl<T> Line<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    // A frame is only complete once the line has been idle for the frame gap
+    // (t3.5 on a serial line), so bytes split by a longer gap never belong to
+    // the same frame.
+    async fn read_frame(&mut self, slave_id: u8, frame_gap: Duration) -> Result<Response, Error> {
+        self.buffer.clear();
+
+        loop {
+            let n = if self.buffer.is_empty() {
+                self.transport.read_buf(&mut self.buffer).await?
+            } else {
+                match time::timeout(frame_gap, self.transport.read_buf(&mut self.buffer)).await {
+                    Ok(n) => n?,
+                    Err(_) => {
+                        self.buffer.clear();
+                        return Err(Error::new(ErrorKind::InvalidData, "incomplete frame"));
+                    }
+                }
+            };
+            self.last_activity = Instant::now();
+
+            if n == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed"));
+            }
+
+            if let Some(adu) = self.codec.decode(&mut self.buffer)? {
+                if adu.header.slave_id != slave_id {
+                    return Err(Error::new(ErrorKind::InvalidData, "unexpected slave id"));
+                }
+                return Ok(adu.response);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<T> Client for AsyncRtuClient<T>
+where
+    T: Send + AsyncRead + AsyncWrite + Unpin,
+{
+    async fn call(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        let req_adu = RequestAdu {
+            header: Header { slave_id },
+            request,
+        };
+
+        let mut line = self.line.lock().await;
+
+        let mut frame = BytesMut::new();
+        line.codec.encode(req_adu, &mut frame)?;
+
+        time::sleep_until(line.last_activity + self.silent_interval).await;
+        discard_input(&mut line.transport).await?;
+
+        line.transport.write_all(&frame).await?;
+        line.transport.flush().await?;
+        line.last_activity = Instant::now();
+
+        // Whatever arrives after the timeout is discarded before the next request.
+        match time::timeout(self.timeout, line.read_frame(slave_id, self.frame_gap)).await {
+            Ok(rsp) => rsp,
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "request timed out")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio_serial::SerialStream;
+
+    use super::super::super::protocol::{rtu::crc16, Request, Response};
+    use super::super::Client;
+    use super::{silent_interval, AsyncRtuClient};
+
+    #[test]
+    fn interval() {
+        assert_eq!(silent_interval(9600, 11), Duration::from_micros(4010));
+        assert_eq!(silent_interval(115200, 11), Duration::from_micros(1750));
+    }
+
+    #[tokio::test]
+    async fn call_over_pty() {
+        let (master, mut slave) = SerialStream::pair().unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 8];
+            slave.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req[..6], &[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]);
+
+            let mut rsp = vec![0x01, 0x03, 0x04, 0x12, 0x34, 0x56, 0x78];
+            let crc = crc16(&rsp);
+            rsp.extend_from_slice(&crc.to_le_bytes());
+            slave.write_all(&rsp).await.unwrap();
+        });
+
+        let client = AsyncRtuClient::new(master, silent_interval(9600, 11), Duration::from_secs(1));
+        let rsp = client
+            .call(1, Request::ReadHoldingRegisters(0, 2))
+            .await
+            .unwrap();
+        assert!(matches!(
+            rsp,
+            Response::ReadHoldingRegisters(ref regs) if regs == &[0x1234, 0x5678]
+        ));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn late_reply_discarded() {
+        let (master, mut slave) = SerialStream::pair().unwrap();
+        let response = |value: u8| {
+            let mut rsp = vec![0x01, 0x03, 0x02, 0x00, value];
+            let crc = crc16(&rsp);
+            rsp.extend_from_slice(&crc.to_le_bytes());
+            rsp
+        };
+
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 8];
+            slave.read_exact(&mut req).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            slave.write_all(&response(1)).await.unwrap();
+
+            slave.read_exact(&mut req).await.unwrap();
+            slave.write_all(&response(2)).await.unwrap();
+        });
+
+        let client =
+            AsyncRtuClient::new(master, silent_interval(9600, 11), Duration::from_millis(50));
+        let rsp = client.call(1, Request::ReadHoldingRegisters(0, 1)).await;
+        assert_eq!(rsp.err().unwrap().kind(), std::io::ErrorKind::TimedOut);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        // the reply to the first request is not taken for the second one
+        let rsp = client
+            .call(1, Request::ReadHoldingRegisters(0, 1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            rsp,
+            Response::ReadHoldingRegisters(ref regs) if regs == &[2]
+        ));
+
+        server.await.unwrap();
+    }
+}
@@ -1,12 +1,21 @@
 use std::{
-    io::Error,
-    sync::atomic::{AtomicU16, Ordering},
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use futures_util::{sink::SinkExt as _, stream::StreamExt as _};
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_util::codec::Framed;
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tracing::debug;
 
 use super::super::protocol::{
     tcp::{ClientCodec, Header, RequestAdu},
@@ -15,29 +24,117 @@ use super::super::protocol::{
 
 use super::Client;
 
-struct AsyncTcpClient<T> {
-    framed: Framed<T, ClientCodec>,
+// unit id and the waiting caller, keyed by transaction id
+type Pending = HashMap<u16, (u8, oneshot::Sender<Result<Response, Error>>)>;
+
+struct InFlight {
+    requests: StdMutex<Pending>,
+    closed: AtomicBool,
+}
+
+impl InFlight {
+    fn forget(&self, transaction_id: u16) {
+        self.requests.lock().unwrap().remove(&transaction_id);
+    }
+
+    fn fail_all(&self, kind: ErrorKind, msg: &str) {
+        self.closed.store(true, Ordering::Relaxed);
+        let mut requests = self.requests.lock().unwrap();
+        for (_, (_, tx)) in requests.drain() {
+            let _ = tx.send(Err(Error::new(kind, msg)));
+        }
+    }
+}
+
+// Forgets the request once its caller stops waiting, whether the call
+// finished, timed out or was cancelled, so no entry outlives its caller.
+struct PendingGuard<'a> {
+    in_flight: &'a InFlight,
+    transaction_id: u16,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.forget(self.transaction_id);
+    }
+}
+
+// Requests are written as soon as they are issued and a reader task hands
+// each response to the caller with the same transaction id, so several
+// requests can be outstanding on one connection.
+pub struct AsyncTcpClient<T> {
+    writer: Mutex<FramedWrite<WriteHalf<T>, ClientCodec>>,
+    in_flight: Arc<InFlight>,
     transaction_id: AtomicU16,
-    //TODO req list
+    timeout: Duration,
+    reader: JoinHandle<()>,
 }
 
 impl<T> AsyncTcpClient<T>
 where
-    T: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Send + 'static,
 {
-    pub fn _new(transport: T) -> Self {
+    pub fn new(transport: T, timeout: Duration) -> Self {
+        let (reader, writer) = tokio::io::split(transport);
+        let in_flight = Arc::new(InFlight {
+            requests: StdMutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+        });
+
         AsyncTcpClient {
-            framed: Framed::new(transport, ClientCodec::default()),
+            writer: Mutex::new(FramedWrite::new(writer, ClientCodec::default())),
+            in_flight: in_flight.clone(),
             transaction_id: AtomicU16::new(0),
+            timeout,
+            reader: tokio::spawn(Self::dispatch(
+                FramedRead::new(reader, ClientCodec::default()),
+                in_flight,
+            )),
         }
     }
 
-    pub fn next_transaction_id(&self) -> u16 {
-        let transaction_id = self.transaction_id.load(Ordering::Relaxed);
-        self.transaction_id
-            .store(transaction_id.wrapping_add(1), Ordering::Relaxed);
+    async fn dispatch(mut framed: FramedRead<ReadHalf<T>, ClientCodec>, in_flight: Arc<InFlight>) {
+        loop {
+            match framed.next().await {
+                Some(Ok(res_adu)) => {
+                    let Header {
+                        transaction_id,
+                        unit_id,
+                    } = res_adu.header;
+                    let req = in_flight.requests.lock().unwrap().remove(&transaction_id);
 
-        transaction_id
+                    match req {
+                        Some((expected, tx)) if expected == unit_id => {
+                            let _ = tx.send(Ok(res_adu.response));
+                        }
+                        Some((_, tx)) => {
+                            let _ = tx.send(Err(Error::new(
+                                ErrorKind::InvalidData,
+                                "unexpected unit id",
+                            )));
+                        }
+                        None => debug!("drop stray response, transaction id {transaction_id}"),
+                    }
+                }
+                // A stream that failed to decode has lost its framing.
+                Some(Err(err)) => {
+                    in_flight.fail_all(err.kind(), &err.to_string());
+                    break;
+                }
+                None => {
+                    in_flight.fail_all(ErrorKind::UnexpectedEof, "connection closed");
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.in_flight.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn next_transaction_id(&self) -> u16 {
+        self.transaction_id.fetch_add(1, Ordering::Relaxed)
     }
 
     pub fn next_request_adu<'a, R>(&self, slave_id: u8, req: R) -> RequestAdu<'a>
@@ -53,26 +150,166 @@ where
             request: req.into(),
         }
     }
+
+    // Registers the request under a transaction id that is not in flight yet.
+    fn register<'a>(
+        &self,
+        slave_id: u8,
+        request: Request<'a>,
+    ) -> Result<(RequestAdu<'a>, oneshot::Receiver<Result<Response, Error>>), Error> {
+        let mut requests = self.in_flight.requests.lock().unwrap();
+        if requests.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::WouldBlock, "too many requests"));
+        }
+
+        let mut req_adu = self.next_request_adu(slave_id, request);
+        while requests.contains_key(&req_adu.header.transaction_id) {
+            req_adu.header.transaction_id = self.next_transaction_id();
+        }
+
+        let (tx, rx) = oneshot::channel();
+        requests.insert(req_adu.header.transaction_id, (slave_id, tx));
+
+        Ok((req_adu, rx))
+    }
+}
+
+impl<T> Drop for AsyncTcpClient<T> {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
 }
 
 #[async_trait]
 impl<T> Client for AsyncTcpClient<T>
 where
-    T: Send + AsyncRead + AsyncWrite + Unpin,
+    T: Send + AsyncRead + AsyncWrite + 'static,
 {
-    async fn call(&mut self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
-        let req_adu = self.next_request_adu(slave_id, request);
-        let _req_hdr = req_adu.header;
+    async fn call(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        if self.is_closed() {
+            return Err(Error::new(ErrorKind::NotConnected, "connection closed"));
+        }
+
+        let (req_adu, rx) = self.register(slave_id, request)?;
+        let _pending = PendingGuard {
+            in_flight: &self.in_flight,
+            transaction_id: req_adu.header.transaction_id,
+        };
+
+        self.writer.lock().await.send(req_adu).await?;
+
+        // A late response finds no entry and is dropped by the reader.
+        match time::timeout(self.timeout, rx).await {
+            Ok(Ok(rsp)) => rsp,
+            Ok(Err(_)) => Err(Error::new(
+                ErrorKind::ConnectionAborted,
+                "connection closed",
+            )),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "request timed out")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    use super::super::super::protocol::{Request, Response};
+    use super::super::Client;
+    use super::AsyncTcpClient;
+
+    fn write_single_register(transaction_id: u16, value: u8) -> [u8; 12] {
+        let [hi, lo] = transaction_id.to_be_bytes();
+        [
+            hi, lo, 0x00, 0x00, 0x00, 0x06, 0x01, 0x06, 0x00, 0x01, 0x00, value,
+        ]
+    }
+
+    #[tokio::test]
+    async fn out_of_order_responses() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let client = AsyncTcpClient::new(client, Duration::from_secs(1));
+
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 24];
+            server.read_exact(&mut req).await.unwrap();
+            assert_eq!(req[..12], write_single_register(0, 1));
+            assert_eq!(req[12..], write_single_register(1, 2));
+
+            server
+                .write_all(&write_single_register(7, 9))
+                .await
+                .unwrap();
+            server.write_all(&req[12..]).await.unwrap();
+            server.write_all(&req[..12]).await.unwrap();
+            server
+        });
+
+        let (first, second) = tokio::join!(
+            client.call(1, Request::WriteSingleRegister(1, 1)),
+            client.call(1, Request::WriteSingleRegister(1, 2)),
+        );
+        assert!(matches!(first, Ok(Response::WriteSingleRegister(1, 1))));
+        assert!(matches!(second, Ok(Response::WriteSingleRegister(1, 2))));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn timeout_drops_late_response() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let client = AsyncTcpClient::new(client, Duration::from_millis(50));
+
+        let rsp = client.call(1, Request::WriteSingleRegister(1, 1)).await;
+        assert_eq!(rsp.err().unwrap().kind(), std::io::ErrorKind::TimedOut);
+
+        let mut req = [0u8; 12];
+        server.read_exact(&mut req).await.unwrap();
+        server.write_all(&req).await.unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 12];
+            server.read_exact(&mut req).await.unwrap();
+            server.write_all(&req).await.unwrap();
+            server
+        });
+
+        let rsp = client.call(1, Request::WriteSingleRegister(1, 2)).await;
+        assert!(matches!(rsp, Ok(Response::WriteSingleRegister(1, 2))));
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled_call_forgets_request() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let client = AsyncTcpClient::new(client, Duration::from_secs(1));
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(50),
+            client.call(1, Request::WriteSingleRegister(1, 1)),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert!(client.in_flight.requests.lock().unwrap().is_empty());
+
+        // the late response is dropped and the next request still matches
+        let mut req = [0u8; 12];
+        server.read_exact(&mut req).await.unwrap();
+        server.write_all(&req).await.unwrap();
 
-        self.framed.read_buffer_mut().clear();
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 12];
+            server.read_exact(&mut req).await.unwrap();
+            server.write_all(&req).await.unwrap();
+            server
+        });
 
-        self.framed.send(req_adu).await?;
-        let res_adu = self
-            .framed
-            .next()
-            .await
-            .ok_or_else(|| Error::last_os_error())??;
+        let rsp = client.call(1, Request::WriteSingleRegister(1, 2)).await;
+        assert!(matches!(rsp, Ok(Response::WriteSingleRegister(1, 2))));
 
-        Ok(res_adu.response)
+        server.await.unwrap();
     }
 }
@@ -0,0 +1,130 @@
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::time;
+use tokio_util::codec::{Decoder as _, Encoder as _};
+
+use super::super::protocol::{
+    tcp::{ClientCodec, Header, RequestAdu},
+    Request, Response,
+};
+
+use super::Client;
+
+// Largest MBAP frame: 7 byte header plus a 253 byte PDU.
+const MAX_DATAGRAM_LEN: usize = 260;
+
+pub struct AsyncUdpClient {
+    socket: UdpSocket,
+    codec: Mutex<ClientCodec>,
+    transaction_id: AtomicU16,
+    timeout: Duration,
+}
+
+impl AsyncUdpClient {
+    // The socket must already be connected to the server address.
+    pub fn new(socket: UdpSocket, timeout: Duration) -> Self {
+        AsyncUdpClient {
+            socket,
+            codec: Mutex::new(ClientCodec::default()),
+            transaction_id: AtomicU16::new(0),
+            timeout,
+        }
+    }
+
+    fn next_transaction_id(&self) -> u16 {
+        self.transaction_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl Client for AsyncUdpClient {
+    async fn call(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        // One exchange at a time, otherwise concurrent callers would consume
+        // each other's datagrams.
+        let mut codec = self.codec.lock().await;
+
+        let header = Header {
+            transaction_id: self.next_transaction_id(),
+            unit_id: slave_id,
+        };
+
+        let mut frame = BytesMut::new();
+        codec.encode(RequestAdu { header, request }, &mut frame)?;
+        self.socket.send(&frame).await?;
+
+        // Each datagram holds exactly one ADU; answers to earlier, timed out
+        // requests can still arrive and are skipped by transaction id.
+        let exchange = async {
+            loop {
+                let mut datagram = BytesMut::zeroed(MAX_DATAGRAM_LEN);
+                let n = self.socket.recv(&mut datagram).await?;
+                datagram.truncate(n);
+
+                let res_adu = codec
+                    .decode(&mut datagram)?
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "incomplete datagram"))?;
+
+                if res_adu.header.transaction_id != header.transaction_id {
+                    continue;
+                }
+                if res_adu.header.unit_id != slave_id {
+                    return Err(Error::new(ErrorKind::InvalidData, "unexpected unit id"));
+                }
+
+                return Ok(res_adu.response);
+            }
+        };
+
+        time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, "request timed out"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use super::super::super::protocol::{Request, Response};
+    use super::super::Client;
+    use super::AsyncUdpClient;
+
+    #[tokio::test]
+    async fn call_skips_stale_datagram() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(server.local_addr().unwrap()).await.unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 32];
+            let (n, peer) = server.recv_from(&mut req).await.unwrap();
+            assert_eq!(
+                &req[..n],
+                &[0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x01, 0x06, 0x00, 0x01, 0x00, 0x03]
+            );
+
+            let stale = [
+                0xFF, 0xFF, 0x00, 0x00, 0x00, 0x06, 0x01, 0x06, 0x00, 0x01, 0x00, 0x09,
+            ];
+            server.send_to(&stale, peer).await.unwrap();
+            server.send_to(&req[..n], peer).await.unwrap();
+        });
+
+        let client = AsyncUdpClient::new(socket, Duration::from_secs(1));
+        let rsp = client
+            .call(1, Request::WriteSingleRegister(1, 3))
+            .await
+            .unwrap();
+        assert!(matches!(rsp, Response::WriteSingleRegister(1, 3)));
+
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_serial::SerialPortBuilderExt as _;
+use tracing::{info, warn};
+
+use crate::module::driver::{DriverStats, LinkState};
+
+use super::client::{
+    ascii::AsyncAsciiClient, rtu::AsyncRtuClient, tcp::AsyncTcpClient, udp::AsyncUdpClient, Client,
+};
+use super::modbus_rtu::{SerialMode, SerialSetting};
+use super::modbus_tcp::{Encapsulation, NetworkSetting};
+use super::protocol::{Request, Response};
+
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+// Opening a serial port does not depend on the slaves answering.
+const SERIAL_OPEN_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[async_trait]
+pub trait Connector: Send + Sync + 'static {
+    fn name(&self) -> String;
+    fn connect_timeout(&self) -> Duration;
+    async fn connect(&self) -> Result<Arc<dyn Client>, Error>;
+}
+
+#[async_trait]
+impl Connector for NetworkSetting {
+    fn name(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    async fn connect(&self) -> Result<Arc<dyn Client>, Error> {
+        let address = (self.host.as_str(), self.port);
+
+        let client: Arc<dyn Client> = match self.encapsulation {
+            Encapsulation::Tcp => {
+                let stream = TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Arc::new(AsyncTcpClient::new(stream, self.response_timeout))
+            }
+            Encapsulation::RtuOverTcp => {
+                let stream = TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Arc::new(AsyncRtuClient::over_tcp(stream, self.response_timeout))
+            }
+            Encapsulation::Udp => {
+                let peer = lookup_host(address)
+                    .await?
+                    .next()
+                    .ok_or_else(|| Error::new(ErrorKind::NotFound, "host not found"))?;
+                let local = if peer.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(peer).await?;
+                Arc::new(AsyncUdpClient::new(socket, self.response_timeout))
+            }
+        };
+
+        Ok(client)
+    }
+}
+
+#[async_trait]
+impl Connector for SerialSetting {
+    fn name(&self) -> String {
+        self.port.clone()
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        SERIAL_OPEN_TIMEOUT
+    }
+
+    async fn connect(&self) -> Result<Arc<dyn Client>, Error> {
+        let stream = tokio_serial::new(&self.port, self.baud_rate)
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .open_native_async()?;
+
+        let client: Arc<dyn Client> = match self.mode {
+            SerialMode::Rtu => Arc::new(AsyncRtuClient::new(
+                stream,
+                self.silent_interval(),
+                self.response_timeout,
+            )),
+            SerialMode::Ascii => Arc::new(AsyncAsciiClient::new(stream, self.response_timeout)),
+        };
+
+        Ok(client)
+    }
+}
+
+struct Link {
+    client: Mutex<Option<Arc<dyn Client>>>,
+    state: Mutex<LinkState>,
+    lost: Notify,
+    stats: Mutex<DriverStats>,
+}
+
+impl Link {
+    fn set_state(&self, state: LinkState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    fn count(&self, result: &Result<Response, Error>) {
+        let mut stats = self.stats.lock().unwrap();
+
+        stats.requests += 1;
+        match result {
+            Ok(_) => stats.last_success = Some(Utc::now()),
+            Err(err) => {
+                stats.errors += 1;
+                if err.kind() == ErrorKind::TimedOut {
+                    stats.timeouts += 1;
+                }
+                stats.last_error = Some(err.to_string());
+            }
+        }
+    }
+
+    fn drop_client(&self) {
+        if self.client.lock().unwrap().take().is_some() {
+            self.lost.notify_one();
+        }
+    }
+}
+
+// Owns the client of one device: it keeps the link open in the background,
+// reconnecting with exponential backoff whenever the link is lost.
+pub struct Connection {
+    link: Arc<Link>,
+    supervisor: JoinHandle<()>,
+}
+
+impl Connection {
+    pub fn new<C: Connector>(connector: C) -> Self {
+        let link = Arc::new(Link {
+            client: Mutex::new(None),
+            state: Mutex::new(LinkState::Connecting),
+            lost: Notify::new(),
+            stats: Mutex::new(DriverStats::default()),
+        });
+
+        Connection {
+            link: link.clone(),
+            supervisor: tokio::spawn(Self::supervise(connector, link)),
+        }
+    }
+
+    pub fn state(&self) -> LinkState {
+        *self.link.state.lock().unwrap()
+    }
+
+    pub fn stats(&self) -> DriverStats {
+        self.link.stats.lock().unwrap().clone()
+    }
+
+    async fn request(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        let client = self
+            .link
+            .client
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::NotConnected, "not connected"))?;
+
+        // Each client times out its own requests after the response timeout.
+        let result = client.call(slave_id, request).await;
+        if let Err(err) = &result {
+            if is_link_error(err) {
+                self.link.drop_client();
+            }
+        }
+        result
+    }
+
+    async fn supervise<C: Connector>(connector: C, link: Arc<Link>) {
+        let mut backoff = BACKOFF_MIN;
+
+        loop {
+            link.set_state(LinkState::Connecting);
+
+            match time::timeout(connector.connect_timeout(), connector.connect()).await {
+                Ok(Ok(client)) => {
+                    info!("connected to {}", connector.name());
+                    *link.client.lock().unwrap() = Some(client);
+                    link.set_state(LinkState::Connected);
+                    backoff = BACKOFF_MIN;
+
+                    link.lost.notified().await;
+                    warn!("connection to {} lost", connector.name());
+                }
+                Ok(Err(err)) => warn!("connect to {} failed, {err}", connector.name()),
+                Err(_) => warn!("connect to {} timed out", connector.name()),
+            }
+
+            link.set_state(LinkState::Backoff);
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.supervisor.abort();
+    }
+}
+
+fn is_link_error(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::UnexpectedEof
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NotConnected
+    )
+}
+
+#[async_trait]
+impl Client for Connection {
+    async fn call(&self, slave_id: u8, request: Request<'_>) -> Result<Response, Error> {
+        let result = self.request(slave_id, request).await;
+        self.link.count(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio::net::TcpListener;
+
+    use crate::module::driver::LinkState;
+
+    use super::super::client::Client;
+    use super::super::modbus_tcp::{Encapsulation, NetworkSetting};
+    use super::super::protocol::{Request, Response};
+    use super::Connection;
+
+    #[tokio::test]
+    async fn reconnect_after_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let connection = Connection::new(NetworkSetting {
+            encapsulation: Encapsulation::Tcp,
+            host: "127.0.0.1".to_string(),
+            port,
+            connect_timeout: Duration::from_secs(1),
+            response_timeout: Duration::from_secs(1),
+            read_gap: 0,
+        });
+
+        // first connection is closed by the server straight away
+        let (stream, _) = listener.accept().await.unwrap();
+        drop(stream);
+        while connection.state() != LinkState::Connected {
+            tokio::task::yield_now().await;
+        }
+        assert!(connection
+            .call(1, Request::WriteSingleRegister(1, 1))
+            .await
+            .is_err());
+        while connection.state() != LinkState::Backoff {
+            tokio::task::yield_now().await;
+        }
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        while connection.state() != LinkState::Connected {
+            tokio::task::yield_now().await;
+        }
+        let server = tokio::spawn(async move {
+            let mut req = [0u8; 12];
+            stream.read_exact(&mut req).await.unwrap();
+            stream.write_all(&req).await.unwrap();
+            stream
+        });
+
+        let rsp = connection.call(1, Request::WriteSingleRegister(1, 2)).await;
+        assert!(matches!(rsp, Ok(Response::WriteSingleRegister(1, 2))));
+
+        let stats = connection.stats();
+        assert_eq!((stats.requests, stats.errors, stats.timeouts), (2, 1, 0));
+        assert!(stats.last_error.is_some() && stats.last_success.is_some());
+
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::error::*;
+use crate::module::driver::{
+    Capabilities, Driver, DriverInfo, DriverStats, LinkState, Parameter, Setting, Tag as DTag,
+    Validate,
+};
+use crate::module::tag::{QualityReason, TagValue};
+use crate::module::value::Value;
+
+use super::access::{bad, read_tags, write_tag};
+use super::connection::{Connection, Connector};
+use super::{check_tags, table_parameter};
+
+// What tells the Modbus drivers apart: the setting of their transport, which
+// is also what they connect with.
+pub trait Transport: Connector + Clone + for<'a> TryFrom<&'a Setting, Error = XError> {
+    const NAME: &'static str;
+    const DESCRIPTION: &'static str;
+
+    fn read_gap(&self) -> u16;
+}
+
+struct Context {
+    connection: Connection,
+    read_gap: u16,
+}
+
+pub struct Modbus<S> {
+    setting: Mutex<Option<S>>,
+    context: Mutex<Option<Arc<Context>>>,
+}
+
+impl<S> Default for Modbus<S> {
+    fn default() -> Self {
+        Modbus {
+            setting: Mutex::new(None),
+            context: Mutex::new(None),
+        }
+    }
+}
+
+impl<S> Modbus<S> {
+    fn context(&self) -> XResult<Arc<Context>> {
+        self.context
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| XError::new(XErrorKind::DeviceError, "device is not configured"))
+    }
+}
+
+#[async_trait]
+impl<S: Transport> Driver for Modbus<S> {
+    fn info(&self) -> DriverInfo {
+        DriverInfo {
+            name: S::NAME.to_string(),
+            description: S::DESCRIPTION.to_string(),
+            version: "0.1.0".to_string(),
+            capabilities: Capabilities {
+                read: true,
+                write: true,
+                subscribe: false,
+            },
+        }
+    }
+
+    fn setting(&self, parameters: &Setting) -> XResult<()> {
+        let setting = S::try_from(parameters)?;
+        *self.setting.lock().unwrap() = Some(setting);
+        Ok(())
+    }
+
+    async fn start(&self) -> XResult<()> {
+        let Some(setting) = self.setting.lock().unwrap().clone() else {
+            // reads report the missing setting
+            return Ok(());
+        };
+        *self.context.lock().unwrap() = Some(Arc::new(Context {
+            read_gap: setting.read_gap(),
+            connection: Connection::new(setting),
+        }));
+        Ok(())
+    }
+
+    async fn stop(&self) {
+        self.context.lock().unwrap().take();
+    }
+
+    fn link_state(&self) -> LinkState {
+        self.context
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(LinkState::Disabled, |ctx| ctx.connection.state())
+    }
+
+    fn stats(&self) -> DriverStats {
+        self.context
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or_else(DriverStats::default, |ctx| ctx.connection.stats())
+    }
+
+    async fn read(&self, tags: &[DTag]) -> Vec<TagValue> {
+        match self.context() {
+            Ok(ctx) => read_tags(&ctx.connection, ctx.read_gap, tags).await,
+            // not configured or stopped
+            Err(_) => tags
+                .iter()
+                .map(|tag| bad(tag, QualityReason::ConfigError))
+                .collect(),
+        }
+    }
+
+    async fn write(&self, tag: &DTag, value: &Value) -> XResult<()> {
+        let ctx = self.context()?;
+        write_tag(&ctx.connection, tag, value).await
+    }
+}
+
+impl<S: Transport> Validate for Modbus<S> {
+    fn table_parameter(&self, parameter: &Parameter) -> XResult<()> {
+        table_parameter(parameter)
+    }
+
+    fn tag(&self, tags: &[DTag]) -> XResult<()> {
+        check_tags(tags)
+    }
+}
@@ -1,14 +1,155 @@
+mod access;
 pub mod client;
+pub mod connection;
+mod driver;
 pub mod protocol;
 
+pub mod modbus_rtu;
 pub mod modbus_tcp;
+pub mod planner;
+mod value;
+
+use std::time::Duration;
 
 use crate::error::*;
 
-use crate::module::driver::Tag;
-use crate::module::value::Value;
+use crate::module::driver::{Parameter, Tag};
+use crate::module::registry::Registry;
+use crate::module::value::{SimpleValue, Value, ValueType};
 
-#[derive(PartialEq, Debug)]
+pub fn register(registry: &mut Registry) {
+    registry.register_default::<modbus_tcp::ModbusTcp>();
+    registry.register_default::<modbus_rtu::ModbusRtu>();
+}
+
+fn invalid_parameter(option: &str) -> XError {
+    XError::new(
+        XErrorKind::ParameterError,
+        &format!("invalid parameter: {option}"),
+    )
+}
+
+fn duration_parameter(option: &str, ms: i64) -> XResult<Duration> {
+    u64::try_from(ms)
+        .ok()
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis)
+        .ok_or_else(|| invalid_parameter(option))
+}
+
+// Tables only take their poll interval in milliseconds.
+fn table_parameter(parameter: &Parameter) -> XResult<()> {
+    match (parameter.option.as_str(), &parameter.value) {
+        ("interval", SimpleValue::INT(ms)) => duration_parameter("interval", *ms).map(|_| ()),
+        (option, _) => Err(invalid_parameter(option)),
+    }
+}
+
+// Checks the tags the way they are read: the value must be of the tag type
+// and the address must suit it and fit in a single read.
+fn check_tags(tags: &[Tag]) -> XResult<()> {
+    tags.iter().try_for_each(|tag| {
+        check_tag(tag).map_err(|err| match err {
+            XError::TagError(_, msg) => {
+                XError::new(XErrorKind::TagError, &format!("{}: {msg}", tag.name))
+            }
+            err => err,
+        })
+    })
+}
+
+fn check_tag(tag: &Tag) -> XResult<()> {
+    if ValueType::from(tag.dtype) != tag.value.v_type() {
+        return Err(XError::new(
+            XErrorKind::TagError,
+            "value does not match the data type",
+        ));
+    }
+
+    if let Some(service) = Service::parse(tag) {
+        return service?.check(tag.value.v_type());
+    }
+
+    let address = Address::try_from(tag)?;
+    if u32::from(address.quantity) > planner::max_quantity(address.area) {
+        return Err(XError::new(
+            XErrorKind::TagError,
+            "tag exceeds the read limit",
+        ));
+    }
+
+    Ok(())
+}
+
+// Tags answered by a function of the slave instead of one of its data areas:
+// an object of Read Device Identification, <slave>.ID.<object id>, or a
+// counter of Diagnostics, <slave>.DIAG.<sub-function>. Both are read-only.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Service {
+    Identification { slave: u8, object: u8 },
+    Diagnostic { slave: u8, sub_function: u16 },
+}
+
+impl Service {
+    // None when the address names a data area.
+    fn parse(tag: &Tag) -> Option<XResult<Service>> {
+        let address: Vec<&str> = tag.address.split('.').collect();
+        match address.get(1) {
+            Some(&"ID") | Some(&"DIAG") => Some(Self::from_parts(&address)),
+            _ => None,
+        }
+    }
+
+    fn from_parts(address: &[&str]) -> XResult<Service> {
+        let [slave, service, id] = address else {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                "address must be in the format: <slave>.<ID/DIAG>.<object id/sub-function>",
+            ));
+        };
+        let slave = slave
+            .parse::<u8>()
+            .map_err(|_| XError::new(XErrorKind::TagError, "invalid slave id"))?;
+
+        if *service == "ID" {
+            let object = id
+                .parse::<u8>()
+                .map_err(|_| XError::new(XErrorKind::TagError, "invalid object id"))?;
+            Ok(Service::Identification { slave, object })
+        } else {
+            // only the diagnostic register and the counters are safe to poll,
+            // other sub-functions restart or silence the slave
+            let sub_function = id
+                .parse::<u16>()
+                .ok()
+                .filter(|sub| matches!(sub, 0x02 | 0x0B..=0x12))
+                .ok_or(XError::new(
+                    XErrorKind::TagError,
+                    "sub-function must be 2 or one of the counters 11 - 18",
+                ))?;
+            Ok(Service::Diagnostic {
+                slave,
+                sub_function,
+            })
+        }
+    }
+
+    fn check(&self, vtype: ValueType) -> XResult<()> {
+        let expected = match self {
+            Service::Identification { .. } => ValueType::STRING,
+            Service::Diagnostic { .. } => ValueType::UINT16,
+        };
+        if vtype != expected {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                &format!("unsupport {vtype:?} for the service, need {expected:?}"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub enum Area {
     Coil,
     DiscreteInput,
@@ -32,6 +173,37 @@ impl TryFrom<&str> for Area {
     }
 }
 
+// Byte order of multi-register values, A being the most significant byte.
+// ABCD is big endian, CDAB swaps the registers, BADC swaps the bytes within
+// each register and DCBA is little endian.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Order {
+    #[default]
+    ABCD,
+    CDAB,
+    BADC,
+    DCBA,
+}
+
+impl TryFrom<&str> for Order {
+    type Error = XError;
+
+    fn try_from(value: &str) -> XResult<Self> {
+        use Order::*;
+
+        match value {
+            "ABCD" => Ok(ABCD),
+            "CDAB" => Ok(CDAB),
+            "BADC" => Ok(BADC),
+            "DCBA" => Ok(DCBA),
+            _ => Err(XError::new(
+                XErrorKind::TagError,
+                "byte order must be one of: ABCD, CDAB, BADC, DCBA",
+            )),
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct Address {
     slave: u8,
@@ -40,6 +212,7 @@ pub struct Address {
     quantity: u16, // 0x0001 - 0x7D00
     bit: u8,       // 0x00 - 0x0f
     length: u16,
+    order: Order,
 }
 
 impl Address {
@@ -60,6 +233,7 @@ impl Address {
                     quantity: 1,
                     bit: 0,
                     length: 0,
+                    order: Order::ABCD,
                 }),
                 Area::InputRegister | Area::HoldingRegister => {
                     if str_address.len() != 3 {
@@ -69,13 +243,10 @@ impl Address {
                         ));
                     }
                     let bit = str_address[2]
-                        .get(0..1)
-                        .ok_or(XError::new(
-                            XErrorKind::TagError,
-                            "address must be in the format: <slave>.<address>.<bit>",
-                        ))?
                         .parse::<u8>()
-                        .map_err(|_| XError::new(XErrorKind::TagError, "need bit offset"))?;
+                        .ok()
+                        .filter(|bit| *bit < 16)
+                        .ok_or(XError::new(XErrorKind::TagError, "need bit offset"))?;
 
                     Ok(Address {
                         slave,
@@ -84,9 +255,35 @@ impl Address {
                         quantity: 1,
                         bit,
                         length: 0,
+                        order: Order::ABCD,
                     })
                 }
             },
+            UINT8(_) | INT8(_) => match area {
+                Area::Coil | Area::DiscreteInput => Err(XError::new(
+                    XErrorKind::TagError,
+                    "unsupport INT8/UINT8/BYTE for Coil/DiscreteInput",
+                )),
+                // the low byte of the register unless <H> selects the high one
+                Area::HoldingRegister | Area::InputRegister => Ok(Address {
+                    slave,
+                    area,
+                    address: (address - 1) as u16,
+                    quantity: 1,
+                    bit: match str_address.get(2) {
+                        None | Some(&"L") => 0,
+                        Some(&"H") => 8,
+                        Some(_) => {
+                            return Err(XError::new(
+                                XErrorKind::TagError,
+                                "address must be in the format: <slave>.<address>.<H/L>",
+                            ))
+                        }
+                    },
+                    length: 0,
+                    order: Order::ABCD,
+                }),
+            },
             UINT16(_) | INT16(_) => match area {
                 Area::Coil | Area::DiscreteInput => Err(XError::new(
                     XErrorKind::TagError,
@@ -99,6 +296,7 @@ impl Address {
                     quantity: 1,
                     bit: 0,
                     length: 0,
+                    order: Order::ABCD,
                 }),
             },
             UINT32(_) | INT32(_) | FLOAT(_) => match area {
@@ -113,6 +311,10 @@ impl Address {
                     quantity: 2,
                     bit: 0,
                     length: 0,
+                    order: match str_address.get(2) {
+                        Some(order) => Order::try_from(*order)?,
+                        None => Order::ABCD,
+                    },
                 }),
             },
             UINT64(_) | INT64(_) | DOUBLE(_) => match area {
@@ -127,6 +329,10 @@ impl Address {
                     quantity: 4,
                     bit: 0,
                     length: 0,
+                    order: match str_address.get(2) {
+                        Some(order) => Order::try_from(*order)?,
+                        None => Order::ABCD,
+                    },
                 }),
             },
             STRING { .. } => {
@@ -142,28 +348,31 @@ impl Address {
                         "address must be in the format: <slave>.<address>.<length><H/L>",
                     ));
                 }
-                let length = str_address[2]
-                    .get(0..)
-                    .ok_or(XError::new(
-                        XErrorKind::TagError,
-                        "address must be in the format: <slave>.<address>.<length><H/L>",
-                    ))?
+                // <H> puts the first character in the high byte of each
+                // register, <L> in the low byte
+                let (length, order) = match str_address[2].strip_suffix('L') {
+                    Some(length) => (length, Order::BADC),
+                    None => (
+                        str_address[2].strip_suffix('H').unwrap_or(str_address[2]),
+                        Order::ABCD,
+                    ),
+                };
+                let length = length
                     .parse::<u16>()
-                    .map_err(|_| XError::new(XErrorKind::TagError, "need string length"))?;
+                    .ok()
+                    .filter(|length| *length > 0)
+                    .ok_or(XError::new(XErrorKind::TagError, "need string length"))?;
 
                 Ok(Address {
                     slave,
                     area,
                     address: (address - 1) as u16,
-                    quantity: length / 2,
+                    quantity: length.div_ceil(2),
                     bit: 0,
                     length,
+                    order,
                 })
             }
-            _ => Err(XError::new(
-                XErrorKind::TagError,
-                "invalid value type for Modbus",
-            )),
         }
     }
 }
@@ -239,7 +448,7 @@ impl TryFrom<&Tag> for Address {
 
 #[cfg(test)]
 mod tests {
-    use super::{Address, Area};
+    use super::{Address, Area, Order};
     use crate::error::*;
     use crate::module::driver::Tag;
     use crate::module::value::DataType::*;
@@ -262,6 +471,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_tags() {
+        let tag = |dtype: DataType, address: &str| Tag {
+            name: "test".to_string(),
+            value: dtype.default_value(),
+            dtype,
+            address: address.to_string(),
+        };
+
+        assert!(super::check_tags(&[tag(WORD, "1.41"), tag(STRING, "1.41.250H")]).is_ok());
+        assert!(super::check_tags(&[tag(WORD, "1.41"), tag(WORD, "1.01")]).is_err());
+        // 126 registers do not fit in one read
+        assert!(super::check_tags(&[tag(STRING, "1.41.251H")]).is_err());
+
+        assert!(super::check_tags(&[tag(STRING, "1.ID.0"), tag(WORD, "1.DIAG.11")]).is_ok());
+        assert!(super::check_tags(&[tag(WORD, "1.ID.0")]).is_err());
+        // restarting the slave is not something to poll
+        assert!(super::check_tags(&[tag(WORD, "1.DIAG.1")]).is_err());
+
+        let mut mismatched = tag(WORD, "1.41");
+        mismatched.dtype = DWORD;
+        let err = super::check_tags(&[mismatched]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Tag Error: test: value does not match the data type (-1)"
+        );
+    }
+
     #[test]
     fn tag_parse_error() {
         tag_check(BIT, "1.00", false, None);
@@ -269,6 +506,9 @@ mod tests {
         tag_check(BIT, "1.265537", false, None);
         tag_check(BYTE, "1.01", false, None);
         tag_check(BYTE, "1.11", false, None);
+        tag_check(DWORD, "1.41.ABDC", false, None);
+        tag_check(BOOL, "1.41.16", false, None);
+        tag_check(STRING, "1.41.0H", false, None);
     }
 
     #[test]
@@ -280,6 +520,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(BIT, "1.H010", true, Some(address));
     }
@@ -293,6 +534,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(BIT, "1.01", true, Some(address));
 
@@ -303,6 +545,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(BOOL, "1.01", true, Some(address));
     }
@@ -316,6 +559,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(BIT, "1.11", true, Some(address));
 
@@ -326,6 +570,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(BOOL, "1.11", true, Some(address));
     }
@@ -339,6 +584,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(WORD, "1.31", true, Some(address));
 
@@ -349,6 +595,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(INT, "1.31", true, Some(address));
 
@@ -359,6 +606,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(WORD, "1.31", true, Some(address));
 
@@ -369,6 +617,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(DINT, "1.31", true, Some(address));
 
@@ -379,6 +628,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(UDINT, "1.31", true, Some(address));
 
@@ -389,6 +639,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(FLOAT, "1.31", true, Some(address));
 
@@ -399,6 +650,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(DWORD, "1.31", true, Some(address));
 
@@ -409,6 +661,7 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(LINT, "1.31", true, Some(address));
 
@@ -419,6 +672,7 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(ULINT, "1.31", true, Some(address));
 
@@ -429,6 +683,7 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(DOUBLE, "1.31", true, Some(address));
 
@@ -439,6 +694,7 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(LWORD, "1.31", true, Some(address));
 
@@ -449,6 +705,7 @@ mod tests {
             quantity: 5,
             bit: 0,
             length: 10,
+            order: Order::ABCD,
         };
         tag_check(STRING, "1.31.10", true, Some(address));
 
@@ -459,6 +716,7 @@ mod tests {
             quantity: 5,
             bit: 0,
             length: 10,
+            order: Order::ABCD,
         };
     }
 
@@ -471,6 +729,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(WORD, "1.41", true, Some(address));
 
@@ -481,6 +740,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(INT, "1.41", true, Some(address));
 
@@ -491,6 +751,7 @@ mod tests {
             quantity: 1,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(WORD, "1.41", true, Some(address));
 
@@ -501,6 +762,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(DINT, "1.41", true, Some(address));
 
@@ -511,6 +773,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(UDINT, "1.41", true, Some(address));
 
@@ -521,6 +784,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(FLOAT, "1.41", true, Some(address));
 
@@ -531,6 +795,7 @@ mod tests {
             quantity: 2,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(DWORD, "1.41", true, Some(address));
 
@@ -541,6 +806,7 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(LINT, "1.41", true, Some(address));
 
@@ -551,8 +817,11 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
-        tag_check(UDINT, "1.41", true, Some(address));
+        // four registers hold a 64-bit type, as for the input registers;
+        // a UDINT here only ever takes two
+        tag_check(ULINT, "1.41", true, Some(address));
 
         let address = Address {
             slave: 1,
@@ -561,6 +830,7 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(DOUBLE, "1.41", true, Some(address));
 
@@ -571,6 +841,7 @@ mod tests {
             quantity: 4,
             bit: 0,
             length: 0,
+            order: Order::ABCD,
         };
         tag_check(LWORD, "1.41", true, Some(address));
 
@@ -581,6 +852,7 @@ mod tests {
             quantity: 5,
             bit: 0,
             length: 10,
+            order: Order::ABCD,
         };
         tag_check(STRING, "1.41.10", true, Some(address));
 
@@ -591,6 +863,7 @@ mod tests {
             quantity: 5,
             bit: 0,
             length: 10,
+            order: Order::ABCD,
         };
     }
 }
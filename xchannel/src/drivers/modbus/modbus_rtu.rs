@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use tokio_serial::{DataBits, Parity, StopBits};
+
+use crate::error::*;
+use crate::module::driver::Setting;
+use crate::module::value::SimpleValue;
+
+use super::client::rtu::silent_interval;
+use super::driver::{Modbus, Transport};
+use super::{duration_parameter, invalid_parameter};
+
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SerialMode {
+    Rtu,
+    Ascii,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerialSetting {
+    pub mode: SerialMode,
+    pub port: String,
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub response_timeout: Duration,
+    pub read_gap: u16,
+}
+
+impl Default for SerialSetting {
+    fn default() -> Self {
+        SerialSetting {
+            mode: SerialMode::Rtu,
+            port: String::new(),
+            baud_rate: 9600,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            read_gap: 0,
+        }
+    }
+}
+
+impl SerialSetting {
+    pub fn bits_per_char(&self) -> u32 {
+        let data = match self.data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity = match self.parity {
+            Parity::None => 0,
+            Parity::Odd | Parity::Even => 1,
+        };
+        let stop = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+
+        1 + data + parity + stop
+    }
+
+    pub fn silent_interval(&self) -> Duration {
+        silent_interval(self.baud_rate, self.bits_per_char())
+    }
+}
+
+impl TryFrom<&Setting> for SerialSetting {
+    type Error = XError;
+
+    fn try_from(setting: &Setting) -> XResult<Self> {
+        let mut serial = SerialSetting::default();
+
+        for parameter in setting {
+            let option = parameter.option.as_str();
+            match (option, &parameter.value) {
+                ("mode", SimpleValue::STRING(mode)) => {
+                    serial.mode = match mode.to_uppercase().as_str() {
+                        "RTU" => SerialMode::Rtu,
+                        "ASCII" => SerialMode::Ascii,
+                        _ => return Err(invalid_parameter(option)),
+                    }
+                }
+                ("port", SimpleValue::STRING(port)) => serial.port = port.clone(),
+                ("baud_rate", SimpleValue::INT(baud_rate)) => {
+                    serial.baud_rate = u32::try_from(*baud_rate)
+                        .ok()
+                        .filter(|b| *b > 0)
+                        .ok_or_else(|| invalid_parameter(option))?
+                }
+                ("data_bits", SimpleValue::INT(bits)) => {
+                    serial.data_bits = match bits {
+                        5 => DataBits::Five,
+                        6 => DataBits::Six,
+                        7 => DataBits::Seven,
+                        8 => DataBits::Eight,
+                        _ => return Err(invalid_parameter(option)),
+                    }
+                }
+                ("parity", SimpleValue::STRING(parity)) => {
+                    serial.parity = match parity.to_lowercase().as_str() {
+                        "none" => Parity::None,
+                        "odd" => Parity::Odd,
+                        "even" => Parity::Even,
+                        _ => return Err(invalid_parameter(option)),
+                    }
+                }
+                ("stop_bits", SimpleValue::INT(bits)) => {
+                    serial.stop_bits = match bits {
+                        1 => StopBits::One,
+                        2 => StopBits::Two,
+                        _ => return Err(invalid_parameter(option)),
+                    }
+                }
+                ("response_timeout", SimpleValue::INT(ms)) => {
+                    serial.response_timeout = duration_parameter(option, *ms)?
+                }
+                ("read_gap", SimpleValue::INT(gap)) => {
+                    serial.read_gap = u16::try_from(*gap).map_err(|_| invalid_parameter(option))?
+                }
+                _ => return Err(invalid_parameter(option)),
+            }
+        }
+
+        if serial.port.is_empty() {
+            return Err(XError::new(
+                XErrorKind::ParameterError,
+                "serial port is required",
+            ));
+        }
+
+        Ok(serial)
+    }
+}
+
+impl Transport for SerialSetting {
+    const NAME: &'static str = "Modbus RTU";
+    const DESCRIPTION: &'static str = "Modbus RTU/ASCII over serial line";
+
+    fn read_gap(&self) -> u16 {
+        self.read_gap
+    }
+}
+
+pub type ModbusRtu = Modbus<SerialSetting>;
@@ -1,71 +1,92 @@
-use crate::module::driver::{Driver, DriverInfo, Tag as DTag, Validate};
+use std::time::Duration;
 
-use crate::error::XResult;
-use crate::module::driver::{Parameter, Setting};
+use crate::error::*;
+use crate::module::driver::Setting;
+use crate::module::value::SimpleValue;
 
-//pub struct Setting {
-//pub host: String,
-//pub port: u16,
-//pub timeout: u16,
-//}
+use super::driver::{Modbus, Transport};
+use super::{duration_parameter, invalid_parameter};
 
-//use crate::driver::dto;
+const DEFAULT_PORT: u16 = 502;
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
 
-pub struct ModbusTcpContext {}
-
-pub struct ModbusTcp {
-    pub setting: Option<Setting>,
-    pub context: Option<ModbusTcpContext>,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encapsulation {
+    Tcp,
+    RtuOverTcp,
+    Udp,
 }
 
-impl Default for ModbusTcp {
-    fn default() -> Self {
-        ModbusTcp {
-            setting: None,
-            context: None,
-        }
-    }
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkSetting {
+    pub encapsulation: Encapsulation,
+    pub host: String,
+    pub port: u16,
+    pub connect_timeout: Duration,
+    pub response_timeout: Duration,
+    pub read_gap: u16,
 }
 
-impl ModbusTcp {
-    pub fn new() -> impl Driver {
-        ModbusTcp {
-            setting: None,
-            context: None,
-        }
-    }
-}
+impl TryFrom<&Setting> for NetworkSetting {
+    type Error = XError;
 
-impl Driver for ModbusTcp {
-    fn info(&self) -> DriverInfo {
-        DriverInfo {
-            name: "Modbus TCP".to_string(),
-            description: "Modbus TCP description".to_string(),
-            version: "0.1.0".to_string(),
-        }
-    }
+    fn try_from(setting: &Setting) -> XResult<Self> {
+        let mut network = NetworkSetting {
+            encapsulation: Encapsulation::Tcp,
+            host: String::new(),
+            port: DEFAULT_PORT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            read_gap: 0,
+        };
 
-    //fn validate(&self, _tags: Vec<crate::tag::Tag>) -> XResult<()> {
-    ////for (i, tag) in tags.iter().enumerate() {
-    ////if let Err(err::XError) = tag.try_into() {
-    ////return Err(err.with_index(i as i32 + 1));
-    ////}
-    ////}
+        for parameter in setting {
+            let option = parameter.option.as_str();
+            match (option, &parameter.value) {
+                ("encapsulation", SimpleValue::STRING(encapsulation)) => {
+                    network.encapsulation = match encapsulation.to_uppercase().as_str() {
+                        "TCP" | "MBAP" => Encapsulation::Tcp,
+                        "RTU OVER TCP" | "RTU_OVER_TCP" => Encapsulation::RtuOverTcp,
+                        "UDP" => Encapsulation::Udp,
+                        _ => return Err(invalid_parameter(option)),
+                    }
+                }
+                ("host", SimpleValue::STRING(host)) => network.host = host.clone(),
+                ("port", SimpleValue::INT(port)) => {
+                    network.port = u16::try_from(*port)
+                        .ok()
+                        .filter(|p| *p > 0)
+                        .ok_or_else(|| invalid_parameter(option))?
+                }
+                ("connect_timeout", SimpleValue::INT(ms)) => {
+                    network.connect_timeout = duration_parameter(option, *ms)?
+                }
+                ("response_timeout", SimpleValue::INT(ms)) => {
+                    network.response_timeout = duration_parameter(option, *ms)?
+                }
+                ("read_gap", SimpleValue::INT(gap)) => {
+                    network.read_gap = u16::try_from(*gap).map_err(|_| invalid_parameter(option))?
+                }
+                _ => return Err(invalid_parameter(option)),
+            }
+        }
 
-    //Ok(())
-    //}
+        if network.host.is_empty() {
+            return Err(XError::new(XErrorKind::ParameterError, "host is required"));
+        }
 
-    fn setting(&self, _parameters: &Setting) -> XResult<()> {
-        Ok(())
+        Ok(network)
     }
 }
 
-impl Validate for ModbusTcp {
-    fn table_parameter(&self, _parameter: &Parameter) -> XResult<()> {
-        Ok(())
-    }
+impl Transport for NetworkSetting {
+    const NAME: &'static str = "Modbus TCP";
+    const DESCRIPTION: &'static str = "Modbus TCP description";
 
-    fn tag(&self, _tags: &[DTag]) -> XResult<()> {
-        Ok(())
+    fn read_gap(&self) -> u16 {
+        self.read_gap
     }
 }
+
+pub type ModbusTcp = Modbus<NetworkSetting>;
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+use crate::error::*;
+use crate::module::driver::Tag;
+use crate::module::value::{Value, ValueType};
+
+use super::{Address, Area};
+
+// Largest quantities a single read PDU can carry.
+const MAX_READ_REGISTERS: u32 = 125;
+const MAX_READ_BITS: u32 = 2000;
+
+pub(super) fn max_quantity(area: Area) -> u32 {
+    match area {
+        Area::Coil | Area::DiscreteInput => MAX_READ_BITS,
+        Area::InputRegister | Area::HoldingRegister => MAX_READ_REGISTERS,
+    }
+}
+
+pub struct Slot {
+    pub index: usize,
+    pub address: Address,
+    pub vtype: ValueType,
+}
+
+// One read request and the tags it serves.
+pub struct ReadBatch {
+    pub slave: u8,
+    pub area: Area,
+    pub address: u16,
+    pub quantity: u16,
+    pub slots: Vec<Slot>,
+}
+
+impl ReadBatch {
+    fn slice<'a, T>(&self, data: &'a [T], address: &Address) -> &'a [T] {
+        let offset = (address.address - self.address) as usize;
+        data.get(offset..).unwrap_or_default()
+    }
+
+    // Values of the tags, keyed by their index in the planned tags.
+    pub fn decode_registers(&self, regs: &[u16]) -> Vec<(usize, XResult<Value>)> {
+        self.slots
+            .iter()
+            .map(|slot| {
+                let regs = self.slice(regs, &slot.address);
+                (slot.index, slot.address.decode_registers(slot.vtype, regs))
+            })
+            .collect()
+    }
+
+    pub fn decode_bits(&self, bits: &[bool]) -> Vec<(usize, XResult<Value>)> {
+        self.slots
+            .iter()
+            .map(|slot| {
+                let bits = self.slice(bits, &slot.address);
+                (slot.index, slot.address.decode_bits(slot.vtype, bits))
+            })
+            .collect()
+    }
+}
+
+// Groups tags by slave and area and merges their ranges into as few reads as
+// the PDU limits allow. Ranges up to `gap` registers (or bits) apart are read
+// together, trading a few unused words for a round trip.
+pub struct Planner {
+    gap: u16,
+}
+
+impl Planner {
+    pub fn new(gap: u16) -> Self {
+        Planner { gap }
+    }
+
+    // Returns the batches and the tags whose address could not be planned.
+    pub fn plan(&self, tags: &[Tag]) -> (Vec<ReadBatch>, Vec<(usize, XError)>) {
+        let mut groups: BTreeMap<(u8, Area), Vec<Slot>> = BTreeMap::new();
+        let mut errors = Vec::new();
+
+        for (index, tag) in tags.iter().enumerate() {
+            match Address::try_from(tag) {
+                Ok(address) if u32::from(address.quantity) > max_quantity(address.area) => {
+                    errors.push((
+                        index,
+                        XError::new(XErrorKind::TagError, "tag exceeds the read limit"),
+                    ));
+                }
+                Ok(address) => groups
+                    .entry((address.slave, address.area))
+                    .or_default()
+                    .push(Slot {
+                        index,
+                        address,
+                        vtype: tag.value.v_type(),
+                    }),
+                Err(err) => errors.push((index, err)),
+            }
+        }
+
+        let mut batches = Vec::new();
+        for ((slave, area), mut slots) in groups {
+            slots.sort_by_key(|slot| slot.address.address);
+
+            let limit = max_quantity(area);
+            let mut current: Option<(u32, u32, Vec<Slot>)> = None;
+
+            for slot in slots {
+                let start = u32::from(slot.address.address);
+                let end = start + u32::from(slot.address.quantity);
+
+                current = match current {
+                    Some((first, last, mut members))
+                        if start <= last + u32::from(self.gap)
+                            && end.max(last) - first <= limit =>
+                    {
+                        members.push(slot);
+                        Some((first, end.max(last), members))
+                    }
+                    previous => {
+                        if let Some(batch) = previous {
+                            batches.push(Self::batch(slave, area, batch));
+                        }
+                        Some((start, end, vec![slot]))
+                    }
+                };
+            }
+
+            if let Some(batch) = current {
+                batches.push(Self::batch(slave, area, batch));
+            }
+        }
+
+        (batches, errors)
+    }
+
+    fn batch(slave: u8, area: Area, (first, last, slots): (u32, u32, Vec<Slot>)) -> ReadBatch {
+        ReadBatch {
+            slave,
+            area,
+            address: first as u16,
+            quantity: (last - first) as u16,
+            slots,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::module::driver::Tag;
+    use crate::module::value::{DataType, Value};
+
+    use super::super::Area;
+    use super::Planner;
+
+    fn tag(dtype: DataType, address: &str) -> Tag {
+        Tag {
+            name: address.to_string(),
+            value: dtype.default_value(),
+            dtype,
+            address: address.to_string(),
+        }
+    }
+
+    fn ranges(planner: &Planner, tags: &[Tag]) -> Vec<(u8, Area, u16, u16)> {
+        let (batches, errors) = planner.plan(tags);
+        assert!(errors.is_empty());
+        batches
+            .iter()
+            .map(|b| (b.slave, b.area, b.address, b.quantity))
+            .collect()
+    }
+
+    #[test]
+    fn merge_adjacent_and_gap() {
+        let tags = [
+            tag(DataType::WORD, "1.41"),
+            tag(DataType::DWORD, "1.42"),
+            tag(DataType::WORD, "1.46"),
+            tag(DataType::WORD, "2.41"),
+            tag(DataType::BOOL, "1.01"),
+            tag(DataType::BOOL, "1.03"),
+        ];
+
+        assert_eq!(
+            ranges(&Planner::new(0), &tags),
+            [
+                (1, Area::Coil, 0, 1),
+                (1, Area::Coil, 2, 1),
+                (1, Area::HoldingRegister, 0, 3),
+                (1, Area::HoldingRegister, 5, 1),
+                (2, Area::HoldingRegister, 0, 1),
+            ]
+        );
+        assert_eq!(
+            ranges(&Planner::new(2), &tags),
+            [
+                (1, Area::Coil, 0, 3),
+                (1, Area::HoldingRegister, 0, 6),
+                (2, Area::HoldingRegister, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_at_pdu_limit() {
+        let tags: Vec<Tag> = (1..=130)
+            .map(|i| tag(DataType::WORD, &format!("1.4{i}")))
+            .collect();
+        assert_eq!(
+            ranges(&Planner::new(0), &tags),
+            [
+                (1, Area::HoldingRegister, 0, 125),
+                (1, Area::HoldingRegister, 125, 5),
+            ]
+        );
+
+        let tags = [tag(DataType::BOOL, "1.01"), tag(DataType::BOOL, "1.02001")];
+        assert_eq!(
+            ranges(&Planner::new(2000), &tags),
+            [(1, Area::Coil, 0, 1), (1, Area::Coil, 2000, 1)]
+        );
+
+        let (_, errors) = Planner::new(0).plan(&[tag(DataType::STRING, "1.41.300")]);
+        assert_eq!(errors[0].0, 0);
+    }
+
+    #[test]
+    fn map_back_to_tags() {
+        let tags = [
+            tag(DataType::DWORD, "1.43.CDAB"),
+            tag(DataType::WORD, "1.41"),
+            tag(DataType::BOOL, "1.41.15"),
+        ];
+        let (batches, _) = Planner::new(1).plan(&tags);
+        assert_eq!(batches.len(), 1);
+
+        let mut values = batches[0].decode_registers(&[0x8001, 0xFFFF, 0x0304, 0x0102]);
+        values.sort_by_key(|(index, _)| *index);
+        let values: Vec<Value> = values.into_iter().map(|(_, v)| v.unwrap()).collect();
+        assert_eq!(
+            values,
+            [
+                Value::UINT32(0x0102_0304),
+                Value::UINT16(0x8001),
+                Value::BOOL(true),
+            ]
+        );
+    }
+}
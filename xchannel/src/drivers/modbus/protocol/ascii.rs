@@ -0,0 +1,220 @@
+use std::io::Error;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{ProtocolError, Request, Response, MAX_PDU_LEN};
+
+const START: u8 = b':';
+const END: &[u8] = b"\r\n";
+const HEX: &[u8; 16] = b"0123456789ABCDEF";
+// start, slave id, PDU, lrc and end, every byte but the delimiters hex encoded
+const MAX_FRAME_LEN: usize = 1 + (MAX_PDU_LEN + 2) * 2 + END.len();
+
+#[derive(Clone, Copy)]
+pub struct Header {
+    pub slave_id: u8,
+}
+
+#[derive(Clone)]
+pub struct RequestAdu<'a> {
+    pub header: Header,
+    pub request: Request<'a>,
+}
+
+pub struct ResponseAdu {
+    pub header: Header,
+    pub response: Response,
+}
+
+pub struct AduDecoder;
+pub struct ClientCodec {
+    pub decoder: AduDecoder,
+}
+
+impl Default for ClientCodec {
+    fn default() -> Self {
+        ClientCodec {
+            decoder: AduDecoder,
+        }
+    }
+}
+
+pub fn lrc(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |acc, b| acc.wrapping_add(*b))
+        .wrapping_neg()
+}
+
+fn from_hex(c: u8) -> Result<u8, ProtocolError> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        _ => Err(ProtocolError::InvalidCharacter),
+    }
+}
+
+fn put_hex(dst: &mut BytesMut, b: u8) {
+    dst.put_u8(HEX[(b >> 4) as usize]);
+    dst.put_u8(HEX[(b & 0x0F) as usize]);
+}
+
+impl Decoder for AduDecoder {
+    type Item = (Header, Bytes);
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Anything in front of the start character is line noise.
+        match src.iter().position(|b| *b == START) {
+            Some(start) => {
+                let _ = src.split_to(start);
+            }
+            None => {
+                src.clear();
+                return Ok(None);
+            }
+        }
+
+        let end = match src.windows(END.len()).position(|w| w == END) {
+            Some(end) => end,
+            // A frame that never ends would grow the buffer without bound.
+            None if src.len() > MAX_FRAME_LEN => {
+                let len = src.len();
+                src.clear();
+                return Err(ProtocolError::InvalidLength(len).into());
+            }
+            None => return Ok(None),
+        };
+
+        let frame = src.split_to(end + END.len());
+        let hex = &frame[1..end];
+        if hex.len() < 6 || frame.len() > MAX_FRAME_LEN || !hex.len().is_multiple_of(2) {
+            return Err(ProtocolError::InvalidLength(frame.len()).into());
+        }
+
+        let mut data = BytesMut::with_capacity(hex.len() / 2);
+        for pair in hex.chunks(2) {
+            data.put_u8(from_hex(pair[0])? << 4 | from_hex(pair[1])?);
+        }
+
+        let checksum = data[data.len() - 1];
+        data.truncate(data.len() - 1);
+        if checksum != lrc(&data) {
+            return Err(ProtocolError::InvalidLrc.into());
+        }
+
+        let header = Header { slave_id: data[0] };
+        let pdu_data = data.split_off(1).freeze();
+
+        Ok(Some((header, pdu_data)))
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = ResponseAdu;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some((header, pdu_data)) = self.decoder.decode(src)? {
+            let response = Response::try_from(pdu_data)?;
+            Ok(Some(ResponseAdu { header, response }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'a> Encoder<RequestAdu<'a>> for ClientCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: RequestAdu<'a>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let RequestAdu { header, request } = item;
+        let pdu_data = Bytes::try_from(request)?;
+        let checksum = lrc(&pdu_data).wrapping_sub(header.slave_id);
+
+        dst.reserve((pdu_data.len() + 2) * 2 + 1 + END.len());
+        dst.put_u8(START);
+        put_hex(dst, header.slave_id);
+        for b in pdu_data.iter() {
+            put_hex(dst, *b);
+        }
+        put_hex(dst, checksum);
+        dst.put_slice(END);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use proptest::prelude::*;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::super::{Request, Response};
+    use super::{lrc, ClientCodec, Header, RequestAdu};
+
+    #[test]
+    fn checksum() {
+        assert_eq!(lrc(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), 0xF2);
+        assert_eq!(lrc(&[0x11, 0x03, 0x00, 0x6B, 0x00, 0x03]), 0x7E);
+    }
+
+    #[test]
+    fn encode_request() {
+        let mut codec = ClientCodec::default();
+        let mut dst = BytesMut::new();
+        codec
+            .encode(
+                RequestAdu {
+                    header: Header { slave_id: 0x01 },
+                    request: Request::ReadHoldingRegisters(0x0000, 0x000A),
+                },
+                &mut dst,
+            )
+            .unwrap();
+        assert_eq!(&dst[..], b":01030000000AF2\r\n");
+    }
+
+    #[test]
+    fn decode_response() {
+        let mut codec = ClientCodec::default();
+        let mut src = BytesMut::from(&b"\x00:010304000A"[..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(b"000BE3\r\n");
+        let adu = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(adu.header.slave_id, 0x01);
+        assert!(matches!(
+            adu.response,
+            Response::ReadHoldingRegisters(ref regs) if regs == &[0x000A, 0x000B]
+        ));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn decode_bad_lrc() {
+        let mut codec = ClientCodec::default();
+        let mut src = BytesMut::from(&b":010600010003F4\r\n"[..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(stream in proptest::collection::vec(any::<u8>(), 0..1200)) {
+            let mut codec = ClientCodec::default();
+            let mut src = BytesMut::from(&stream[..]);
+            while let Ok(Some(_)) = codec.decode(&mut src) {}
+        }
+
+        #[test]
+        fn decode_with_valid_lrc_never_panics(data in proptest::collection::vec(any::<u8>(), 2..300)) {
+            let mut frame = vec![b':'];
+            for b in data.iter().chain(std::iter::once(&lrc(&data))) {
+                frame.extend_from_slice(format!("{:02X}", b).as_bytes());
+            }
+            frame.extend_from_slice(b"\r\n");
+            let _ = ClientCodec::default().decode(&mut BytesMut::from(&frame[..]));
+        }
+    }
+}
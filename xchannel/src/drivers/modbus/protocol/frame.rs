@@ -1,9 +1,23 @@
-use std::io::{Cursor, Error, ErrorKind};
+use std::io::Error;
 
-use byteorder::{BigEndian, ReadBytesExt as _};
+use byteorder::{BigEndian, ByteOrder as _};
 use bytes::{BufMut, Bytes, BytesMut};
 
-use super::{Exception, Request, Response};
+use super::{
+    DeviceIdObject, DeviceIdentification, Exception, ProtocolError, Request, Response, MAX_PDU_LEN,
+};
+
+// MEI type of Read Device Identification
+const MEI_DEVICE_ID: u8 = 0x0E;
+// Largest quantity a single write PDU can carry.
+const MAX_WRITE_REGISTERS: usize = 123;
+
+fn check_quantity(function: u8, quantity: usize, max: usize) -> Result<(), ProtocolError> {
+    if quantity == 0 || quantity > max {
+        return Err(ProtocolError::InvalidQuantity(function, quantity));
+    }
+    Ok(())
+}
 
 fn bool_to_coil(state: bool) -> u16 {
     if state {
@@ -17,31 +31,26 @@ fn coil_to_bool(coil: u16) -> bool {
     coil & 0xFF00 > 0
 }
 
-fn pack_coils(coils: &[bool]) -> Vec<u8> {
-    let mut res = vec![0; (coils.len() + 7) / 8];
-
-    for (i, b) in coils.iter().enumerate() {
-        let v = u8::from(*b);
-        res[i / 8] |= v << (i % 8);
-    }
-
-    res
-}
-
 fn unpack_coils(bytes: &[u8], count: u16) -> Vec<bool> {
-    let mut res = Vec::with_capacity(count as usize);
-    for i in 0usize..count.into() {
+    let count = usize::from(count).min(bytes.len() * 8);
+    let mut res = Vec::with_capacity(count);
+    for i in 0..count {
         res.push((bytes[i / 8] >> (i % 8)) & 0b1 > 0);
     }
     res
 }
 
-impl<'a> From<Request<'a>> for Bytes {
-    fn from(f: Request<'a>) -> Self {
+// Encodes the request PDU; quantities a PDU cannot carry are rejected
+// rather than truncated.
+impl<'a> TryFrom<Request<'a>> for Bytes {
+    type Error = ProtocolError;
+
+    fn try_from(f: Request<'a>) -> Result<Self, ProtocolError> {
         use Request::*;
 
+        let function = f.code();
         let mut data = BytesMut::with_capacity(f.req_size());
-        data.put_u8(f.code());
+        data.put_u8(function);
         match f {
             ReadCoils(address, quantity)
             | ReadDiscreteInputs(address, quantity)
@@ -54,30 +63,98 @@ impl<'a> From<Request<'a>> for Bytes {
                 data.put_u16(address);
                 data.put_u16(bool_to_coil(state));
             }
-            WriteMultipleCoils(address, coils) => {
-                data.put_u16(address);
-                data.put_u16(coils.len() as u16);
-                let packed_coils = pack_coils(coils);
-                data.put_u8(packed_coils.len() as u8);
-                for b in packed_coils {
-                    data.put_u8(b);
-                }
-            }
             WriteSingleRegister(address, word) => {
                 data.put_u16(address);
                 data.put_u16(word);
             }
             WriteMultipleRegisters(address, words) => {
+                check_quantity(function, words.len(), MAX_WRITE_REGISTERS)?;
                 data.put_u16(address);
                 data.put_u16(words.len() as u16);
-                data.put_u8(words.len() as u8 * 2);
+                data.put_u8((words.len() * 2) as u8);
                 for v in words {
                     data.put_u16(*v);
                 }
             }
+            MaskWriteRegister(address, and_mask, or_mask) => {
+                data.put_u16(address);
+                data.put_u16(and_mask);
+                data.put_u16(or_mask);
+            }
+            ReadDeviceIdentification(code, object_id) => {
+                data.put_u8(MEI_DEVICE_ID);
+                data.put_u8(code as u8);
+                data.put_u8(object_id);
+            }
+            Diagnostics(sub_function, word) => {
+                data.put_u16(sub_function);
+                data.put_u16(word);
+            }
         }
 
-        data.freeze()
+        Ok(data.freeze())
+    }
+}
+
+// Bounds-checked view over a response PDU: every read fails with a typed
+// error instead of running past the end of the frame.
+struct PduReader<'a> {
+    function: u8,
+    bytes: &'a [u8],
+}
+
+impl<'a> PduReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ProtocolError> {
+        if self.bytes.len() < n {
+            return Err(ProtocolError::Truncated(self.function));
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(BigEndian::read_u16(self.take(2)?))
+    }
+
+    fn words(&mut self, count: usize) -> Result<Vec<u16>, ProtocolError> {
+        Ok(self
+            .take(count * 2)?
+            .chunks(2)
+            .map(BigEndian::read_u16)
+            .collect())
+    }
+
+    // Byte count that has to cover exactly the rest of the PDU.
+    fn byte_count(&mut self) -> Result<usize, ProtocolError> {
+        let byte_count = self.u8()? as usize;
+        if byte_count != self.bytes.len() {
+            return Err(ProtocolError::ByteCount(self.function, byte_count));
+        }
+        Ok(byte_count)
+    }
+
+    // Byte count of register data: the rest of the PDU and an even number.
+    fn register_byte_count(&mut self) -> Result<usize, ProtocolError> {
+        let byte_count = self.byte_count()?;
+        if !byte_count.is_multiple_of(2) {
+            return Err(ProtocolError::ByteCount(self.function, byte_count));
+        }
+        Ok(byte_count)
+    }
+
+    fn finish(self) -> Result<(), ProtocolError> {
+        if !self.bytes.is_empty() {
+            return Err(ProtocolError::TrailingBytes(
+                self.function,
+                self.bytes.len(),
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -85,84 +162,104 @@ impl TryFrom<Bytes> for Response {
     type Error = Error;
 
     fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
-        use Response::*;
-
-        let mut rdr = Cursor::new(&bytes);
-        let function = rdr.read_u8()?;
-        let rsp = match function {
-            0x01 => {
-                let byte_count = rdr.read_u8()?;
-                let x = &bytes[2..];
+        Ok(decode_response(&bytes)?)
+    }
+}
 
-                let quantity = u16::from(byte_count) * 8;
-                ReadCoils(unpack_coils(x, quantity))
-            }
-            0x02 => {
-                let byte_count = rdr.read_u8()?;
-                let x = &bytes[2..];
+fn decode_response(bytes: &[u8]) -> Result<Response, ProtocolError> {
+    use Response::*;
 
-                let quantity = u16::from(byte_count) * 8;
-                ReadDiscreteInputs(unpack_coils(x, quantity))
-            }
-            0x03 => {
-                let byte_count = rdr.read_u8()?;
-                let quantity = byte_count / 2;
-                let mut data = Vec::with_capacity(quantity as usize);
+    if bytes.len() > MAX_PDU_LEN {
+        return Err(ProtocolError::InvalidLength(bytes.len()));
+    }
+    let function = *bytes.first().ok_or(ProtocolError::InvalidLength(0))?;
+    let mut rdr = PduReader {
+        function,
+        bytes: &bytes[1..],
+    };
 
-                for _ in 0..quantity {
-                    data.push(rdr.read_u16::<BigEndian>()?);
-                }
-                ReadHoldingRegisters(data)
+    let rsp = match function {
+        0x01 => {
+            let byte_count = rdr.byte_count()?;
+            ReadCoils(unpack_coils(rdr.take(byte_count)?, byte_count as u16 * 8))
+        }
+        0x02 => {
+            let byte_count = rdr.byte_count()?;
+            ReadDiscreteInputs(unpack_coils(rdr.take(byte_count)?, byte_count as u16 * 8))
+        }
+        0x03 => {
+            let byte_count = rdr.register_byte_count()?;
+            ReadHoldingRegisters(rdr.words(byte_count / 2)?)
+        }
+        0x04 => {
+            let byte_count = rdr.register_byte_count()?;
+            ReadInputRegisters(rdr.words(byte_count / 2)?)
+        }
+        0x05 => {
+            let address = rdr.u16()?;
+            let value = rdr.u16()?;
+            WriteSingleCoil(address, coil_to_bool(value))
+        }
+        0x06 => {
+            let address = rdr.u16()?;
+            let value = rdr.u16()?;
+            WriteSingleRegister(address, value)
+        }
+        0x10 => {
+            let address = rdr.u16()?;
+            let quantity = rdr.u16()?;
+            WriteMultipleRegisters(address, quantity)
+        }
+        0x16 => {
+            let address = rdr.u16()?;
+            let and_mask = rdr.u16()?;
+            let or_mask = rdr.u16()?;
+            MaskWriteRegister(address, and_mask, or_mask)
+        }
+        // RTU framing has no length for Diagnostics, so only sub-functions
+        // answering with a single data word are supported.
+        0x08 => {
+            let sub_function = rdr.u16()?;
+            let data = rdr.u16()?;
+            Diagnostics(sub_function, data)
+        }
+        0x2B => {
+            let mei = rdr.u8()?;
+            if mei != MEI_DEVICE_ID {
+                return Err(ProtocolError::InvalidMeiType(mei));
             }
-            0x04 => {
-                let byte_count = rdr.read_u8()?;
-                let quantity = byte_count / 2;
-                let mut data = Vec::with_capacity(quantity as usize);
+            // read device id code and conformity level
+            rdr.take(2)?;
+            let more_follows = rdr.u8()? == 0xFF;
+            let next_object_id = rdr.u8()?;
+            let count = rdr.u8()?;
+            let mut objects = Vec::with_capacity(count as usize);
 
-                for _ in 0..quantity {
-                    data.push(rdr.read_u16::<BigEndian>()?);
-                }
-                ReadInputRegisters(data)
-            }
-            0x05 => {
-                let address = rdr.read_u16::<BigEndian>()?;
-                let value = rdr.read_u16::<BigEndian>()?;
-                WriteSingleCoil(address, coil_to_bool(value))
-            }
-            0x06 => {
-                let address = rdr.read_u16::<BigEndian>()?;
-                let value = rdr.read_u16::<BigEndian>()?;
-                WriteSingleRegister(address, value)
-            }
-            0x0F => {
-                let address = rdr.read_u16::<BigEndian>()?;
-                let quantity = rdr.read_u16::<BigEndian>()?;
-                WriteMultipleCoils(address, quantity)
-            }
-            0x10 => {
-                let address = rdr.read_u16::<BigEndian>()?;
-                let quantity = rdr.read_u16::<BigEndian>()?;
-                WriteMultipleRegisters(address, quantity)
+            for _ in 0..count {
+                let id = rdr.u8()?;
+                let len = rdr.u8()? as usize;
+                let value = rdr.take(len)?.to_vec();
+                objects.push(DeviceIdObject { id, value });
             }
-            code if code > 0x80 => {
-                let function = function - 0x80;
-                let exception = Exception::try_from(rdr.read_u8()?)?;
-                ExceptionResponse(function, exception)
-            }
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Invalid function code: {}", function),
-                ))
-            }
-        };
+            ReadDeviceIdentification(DeviceIdentification {
+                more_follows,
+                next_object_id,
+                objects,
+            })
+        }
+        code if code > 0x80 => {
+            let exception = Exception::try_from(rdr.u8()?)?;
+            ExceptionResponse(code - 0x80, exception)
+        }
+        _ => return Err(ProtocolError::InvalidFunction(function)),
+    };
+    rdr.finish()?;
 
-        Ok(rsp)
-    }
+    Ok(rsp)
 }
 
 impl TryFrom<u8> for Exception {
-    type Error = Error;
+    type Error = ProtocolError;
 
     fn try_from(code: u8) -> Result<Self, Self::Error> {
         use Exception::*;
@@ -177,14 +274,163 @@ impl TryFrom<u8> for Exception {
             0x08 => MemoryParityError,
             0x0A => GateWayPathUnavailable,
             0x0B => GatewayTargetDevice,
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Invalid exception code: {}", code),
-                ))
-            }
+            _ => return Err(ProtocolError::InvalidException(code)),
         };
 
         Ok(ex)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use proptest::prelude::*;
+
+    use super::super::{ProtocolError, ReadDeviceIdCode, Request, Response};
+    use super::decode_response;
+
+    fn encode(req: Request) -> Vec<u8> {
+        Bytes::try_from(req).unwrap().to_vec()
+    }
+
+    fn decode(pdu: &[u8]) -> Response {
+        Response::try_from(Bytes::copy_from_slice(pdu)).unwrap()
+    }
+
+    #[test]
+    fn quantity_limits() {
+        let words = [0u16; 128];
+        assert!(matches!(
+            Bytes::try_from(Request::WriteMultipleRegisters(0, &words)),
+            Err(ProtocolError::InvalidQuantity(0x10, 128))
+        ));
+        assert_eq!(
+            encode(Request::WriteMultipleRegisters(0, &words[..123]))[5],
+            246
+        );
+    }
+
+    #[test]
+    fn mask_write_register() {
+        assert_eq!(
+            encode(Request::MaskWriteRegister(0x0004, 0x00F2, 0x0025)),
+            [0x16, 0x00, 0x04, 0x00, 0xF2, 0x00, 0x25]
+        );
+        assert!(matches!(
+            decode(&[0x16, 0x00, 0x04, 0x00, 0xF2, 0x00, 0x25]),
+            Response::MaskWriteRegister(0x0004, 0x00F2, 0x0025)
+        ));
+    }
+
+    #[test]
+    fn read_device_identification() {
+        assert_eq!(
+            encode(Request::ReadDeviceIdentification(
+                ReadDeviceIdCode::Basic,
+                0
+            )),
+            [0x2B, 0x0E, 0x01, 0x00]
+        );
+
+        let rsp = decode(&[
+            0x2B, 0x0E, 0x01, 0x01, 0x00, 0x00, 0x02, 0x00, 0x03, b'A', b'B', b'C', 0x01, 0x01,
+            b'X',
+        ]);
+        if let Response::ReadDeviceIdentification(id) = rsp {
+            assert!(!id.more_follows);
+            assert_eq!(id.objects.len(), 2);
+            assert_eq!(id.objects[0].value, b"ABC");
+            assert_eq!(id.objects[1].id, 0x01);
+        } else {
+            panic!("unexpected response");
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        assert_eq!(
+            encode(Request::Diagnostics(0x0000, 0xA537)),
+            [0x08, 0x00, 0x00, 0xA5, 0x37]
+        );
+        assert!(matches!(
+            decode(&[0x08, 0x00, 0x00, 0xA5, 0x37]),
+            Response::Diagnostics(0x0000, 0xA537)
+        ));
+        assert_eq!(
+            decode_response(&[0x08, 0x00, 0x00, 0xA5, 0x37, 0x00, 0x01]).err(),
+            Some(ProtocolError::TrailingBytes(0x08, 2))
+        );
+    }
+
+    #[test]
+    fn malformed_responses() {
+        // byte count larger than the payload
+        assert_eq!(
+            decode_response(&[0x01, 0x04, 0xFF]).err(),
+            Some(ProtocolError::ByteCount(0x01, 4))
+        );
+        // odd register byte count
+        assert_eq!(
+            decode_response(&[0x03, 0x03, 0x00, 0x01, 0x02]).err(),
+            Some(ProtocolError::ByteCount(0x03, 3))
+        );
+        assert_eq!(
+            decode_response(&[0x06, 0x00, 0x01]).err(),
+            Some(ProtocolError::Truncated(0x06))
+        );
+        assert_eq!(
+            decode_response(&[0x06, 0x00, 0x01, 0x00, 0x03, 0x00]).err(),
+            Some(ProtocolError::TrailingBytes(0x06, 1))
+        );
+        assert_eq!(
+            decode_response(&[0x2B, 0x0E, 0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x09, b'A']).err(),
+            Some(ProtocolError::Truncated(0x2B))
+        );
+        assert_eq!(
+            decode_response(&[0x83, 0x07]).err(),
+            Some(ProtocolError::InvalidException(0x07))
+        );
+        assert_eq!(
+            decode_response(&[]).err(),
+            Some(ProtocolError::InvalidLength(0))
+        );
+
+        let err = Response::try_from(Bytes::from_static(&[0x42]))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<ProtocolError>(),
+            Some(&ProtocolError::InvalidFunction(0x42))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(pdu in proptest::collection::vec(any::<u8>(), 0..300)) {
+            let _ = decode_response(&pdu);
+        }
+
+        #[test]
+        fn decode_known_function_never_panics(
+            function in prop::sample::select(vec![
+                0x01u8, 0x02, 0x03, 0x04, 0x05, 0x06, 0x08, 0x10, 0x16, 0x2B, 0x81, 0x83,
+            ]),
+            body in proptest::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let mut pdu = vec![function];
+            pdu.extend(body);
+            let _ = decode_response(&pdu);
+        }
+
+        #[test]
+        fn registers_round_trip(regs in proptest::collection::vec(any::<u16>(), 0..125)) {
+            let mut pdu = vec![0x03, (regs.len() * 2) as u8];
+            for r in &regs {
+                pdu.extend_from_slice(&r.to_be_bytes());
+            }
+            let rsp = decode_response(&pdu).unwrap();
+            prop_assert!(matches!(rsp, Response::ReadHoldingRegisters(ref r) if r == &regs));
+        }
+    }
+}
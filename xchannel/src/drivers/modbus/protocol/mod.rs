@@ -1,13 +1,75 @@
 use std::fmt::Display;
+use std::io;
 
+use thiserror::Error;
+
+pub mod ascii;
 mod frame;
+pub mod rtu;
 pub mod tcp;
 
-const _MODBUS_MAX_PDU_LEN: usize = 253;
+const MAX_PDU_LEN: usize = 253;
 
 type Quantity = u16;
 type Address = u16;
 
+// Malformed frames from the wire. Decoders hand them to the transport as
+// `io::ErrorKind::InvalidData` with this error as the inner error.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    #[error("truncated response to function 0x{0:02X}")]
+    Truncated(u8),
+    #[error("{1} trailing bytes in response to function 0x{0:02X}")]
+    TrailingBytes(u8, usize),
+    #[error("byte count {1} does not match response to function 0x{0:02X}")]
+    ByteCount(u8, usize),
+    #[error("invalid function code: 0x{0:02X}")]
+    InvalidFunction(u8),
+    #[error("invalid exception code: 0x{0:02X}")]
+    InvalidException(u8),
+    #[error("invalid MEI type: 0x{0:02X}")]
+    InvalidMeiType(u8),
+    #[error("invalid protocol id: {0}")]
+    InvalidProtocolId(u16),
+    #[error("invalid frame length: {0}")]
+    InvalidLength(usize),
+    #[error("invalid character in frame")]
+    InvalidCharacter,
+    #[error("invalid crc")]
+    InvalidCrc,
+    #[error("invalid lrc")]
+    InvalidLrc,
+    #[error("quantity {1} out of range for function 0x{0:02X}")]
+    InvalidQuantity(u8, usize),
+}
+
+impl From<ProtocolError> for io::Error {
+    fn from(err: ProtocolError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadDeviceIdCode {
+    Basic = 0x01,
+    Regular = 0x02,
+    Extended = 0x03,
+    Specific = 0x04,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdObject {
+    pub id: u8,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdentification {
+    pub more_follows: bool,
+    pub next_object_id: u8,
+    pub objects: Vec<DeviceIdObject>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Request<'a> {
     ReadCoils(Address, Quantity),
@@ -16,23 +78,28 @@ pub enum Request<'a> {
     ReadInputRegisters(Address, Quantity),
     WriteSingleCoil(Address, bool),
     WriteSingleRegister(Address, u16),
-    WriteMultipleCoils(Address, &'a [bool]),
     WriteMultipleRegisters(Address, &'a [u16]),
+    MaskWriteRegister(Address, u16, u16),
+    ReadDeviceIdentification(ReadDeviceIdCode, u8),
+    // sub-function and its one data word
+    Diagnostics(u16, u16),
 }
 
 pub enum Response {
     ReadCoils(Vec<bool>),
     ReadDiscreteInputs(Vec<bool>),
     WriteSingleCoil(Address, bool),
-    WriteMultipleCoils(Address, Quantity),
     ReadInputRegisters(Vec<u16>),
     ReadHoldingRegisters(Vec<u16>),
     WriteSingleRegister(Address, u16),
     WriteMultipleRegisters(Address, Quantity),
+    MaskWriteRegister(Address, u16, u16),
+    ReadDeviceIdentification(DeviceIdentification),
+    Diagnostics(u16, u16),
     ExceptionResponse(u8, Exception),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Exception {
     IllegalFunction = 0x01,
     IllegalDataAddress = 0x02,
@@ -73,8 +140,10 @@ impl<'a> Request<'a> {
             ReadInputRegisters(_, _) => 0x04,
             WriteSingleCoil(_, _) => 0x05,
             WriteSingleRegister(_, _) => 0x06,
-            WriteMultipleCoils(_, _) => 0x0F,
             WriteMultipleRegisters(_, _) => 0x10,
+            MaskWriteRegister(_, _, _) => 0x16,
+            ReadDeviceIdentification(_, _) => 0x2B,
+            Diagnostics(_, _) => 0x08,
         }
     }
 
@@ -87,33 +156,29 @@ impl<'a> Request<'a> {
             | ReadInputRegisters(_, _)
             | WriteSingleCoil(_, _)
             | WriteSingleRegister(_, _) => 5,
-            WriteMultipleCoils(_, ref data) => 6 + data.len(),
             WriteMultipleRegisters(_, ref data) => 6 + data.len(),
+            MaskWriteRegister(_, _, _) => 7,
+            ReadDeviceIdentification(_, _) => 4,
+            Diagnostics(_, _) => 5,
         }
     }
 }
 
-//#[derive(Clone)]
-//pub struct RequestPdu<'a>(pub Request<'a>);
-//pub struct ResponsePdu(pub Result<Response, io::Error>);
-
-//impl<'a> From<Request<'a>> for RequestPdu<'a> {
-//fn from(value: Request<'a>) -> Self {
-//RequestPdu(value)
-//}
-//}
-
-//impl Error for ExceptionResponse {
-//fn description(&self) -> &str {
-//self.exception.description()
-//}
-//}
-
-//impl Display for ExceptionResponse {
-//fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//write!(f, "Modbus function {}: {}", self.function, self.exception)
-//}
-//}
+// Exception response of a server, handed to callers as the inner error of an
+// `io::Error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionResponse {
+    pub function: u8,
+    pub exception: Exception,
+}
+
+impl std::error::Error for ExceptionResponse {}
+
+impl Display for ExceptionResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Modbus function {}: {}", self.function, self.exception)
+    }
+}
 
 impl Display for Exception {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -0,0 +1,256 @@
+use std::io::Error;
+
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{ProtocolError, Request, Response, MAX_PDU_LEN};
+
+// slave id + function code + crc
+const MIN_FRAME_LEN: usize = 4;
+const CRC_LEN: usize = 2;
+
+#[derive(Clone, Copy)]
+pub struct Header {
+    pub slave_id: u8,
+}
+
+#[derive(Clone)]
+pub struct RequestAdu<'a> {
+    pub header: Header,
+    pub request: Request<'a>,
+}
+
+pub struct ResponseAdu {
+    pub header: Header,
+    pub response: Response,
+}
+
+pub struct AduDecoder;
+pub struct ClientCodec {
+    pub decoder: AduDecoder,
+}
+
+impl Default for ClientCodec {
+    fn default() -> Self {
+        ClientCodec {
+            decoder: AduDecoder,
+        }
+    }
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for b in data {
+        crc ^= u16::from(*b);
+        for _ in 0..8 {
+            if crc & 0x0001 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+// Read Device Identification carries no byte count, the objects have to be
+// walked to find the end of the PDU.
+fn device_id_pdu_len(pdu: &[u8]) -> Option<usize> {
+    let count = *pdu.get(6)? as usize;
+    let mut len = 7;
+    for _ in 0..count {
+        if len > MAX_PDU_LEN {
+            break;
+        }
+        len += 2 + *pdu.get(len + 1)? as usize;
+    }
+    Some(len)
+}
+
+// RTU frames carry no length field, so the PDU length of a response is
+// derived from its function code (and byte count where there is one).
+fn response_pdu_len(pdu: &[u8]) -> Result<Option<usize>, ProtocolError> {
+    let function = match pdu.first() {
+        Some(function) => *function,
+        None => return Ok(None),
+    };
+
+    let len = match function {
+        0x01..=0x04 => match pdu.get(1) {
+            Some(byte_count) => 2 + *byte_count as usize,
+            None => return Ok(None),
+        },
+        0x05 | 0x06 | 0x08 | 0x10 => 5,
+        0x16 => 7,
+        0x2B => match device_id_pdu_len(pdu) {
+            Some(len) => len,
+            None => return Ok(None),
+        },
+        code if code > 0x80 => 2,
+        _ => return Err(ProtocolError::InvalidFunction(function)),
+    };
+    if len > MAX_PDU_LEN {
+        return Err(ProtocolError::InvalidLength(len));
+    }
+
+    Ok(Some(len))
+}
+
+impl Decoder for AduDecoder {
+    type Item = (Header, Bytes);
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < MIN_FRAME_LEN {
+            return Ok(None);
+        }
+
+        let pdu_len = match response_pdu_len(&src[1..])? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let frame_len = 1 + pdu_len + CRC_LEN;
+        if src.len() < frame_len {
+            return Ok(None);
+        }
+
+        let crc = LittleEndian::read_u16(&src[frame_len - CRC_LEN..frame_len]);
+        if crc != crc16(&src[..frame_len - CRC_LEN]) {
+            return Err(ProtocolError::InvalidCrc.into());
+        }
+
+        let mut frame = src.split_to(frame_len);
+        let header = Header { slave_id: frame[0] };
+        frame.truncate(frame_len - CRC_LEN);
+        let pdu_data = frame.split_off(1).freeze();
+
+        Ok(Some((header, pdu_data)))
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = ResponseAdu;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some((header, pdu_data)) = self.decoder.decode(src)? {
+            let response = Response::try_from(pdu_data)?;
+            Ok(Some(ResponseAdu { header, response }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'a> Encoder<RequestAdu<'a>> for ClientCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: RequestAdu<'a>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let RequestAdu { header, request } = item;
+        let pdu_data = Bytes::try_from(request)?;
+        let start = dst.len();
+        dst.reserve(pdu_data.len() + 1 + CRC_LEN);
+        dst.put_u8(header.slave_id);
+        dst.put_slice(&pdu_data);
+        let crc = crc16(&dst[start..]);
+        dst.put_u16_le(crc);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use proptest::prelude::*;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::super::{Request, Response};
+    use super::{crc16, ClientCodec, Header, RequestAdu};
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), 0xCDC5);
+        assert_eq!(crc16(&[0x11, 0x03, 0x00, 0x6B, 0x00, 0x03]), 0x8776);
+    }
+
+    #[test]
+    fn encode_request() {
+        let mut codec = ClientCodec::default();
+        let mut dst = BytesMut::new();
+        codec
+            .encode(
+                RequestAdu {
+                    header: Header { slave_id: 0x01 },
+                    request: Request::ReadHoldingRegisters(0x0000, 0x000A),
+                },
+                &mut dst,
+            )
+            .unwrap();
+        assert_eq!(&dst[..], &[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD]);
+    }
+
+    #[test]
+    fn decode_response() {
+        let mut codec = ClientCodec::default();
+        let mut src = BytesMut::from(&[0x01, 0x03, 0x04, 0x00, 0x0A][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&[0x00, 0x0B]);
+        let crc = crc16(&src);
+        src.extend_from_slice(&crc.to_le_bytes());
+        let adu = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(adu.header.slave_id, 0x01);
+        assert!(matches!(
+            adu.response,
+            Response::ReadHoldingRegisters(ref regs) if regs == &[0x000A, 0x000B]
+        ));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn decode_device_identification() {
+        let mut codec = ClientCodec::default();
+        let mut src = BytesMut::from(
+            &[
+                0x01, 0x2B, 0x0E, 0x01, 0x01, 0x00, 0x00, 0x02, 0x00, 0x03, b'A', b'B', b'C', 0x01,
+            ][..],
+        );
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&[0x01, b'X']);
+        let crc = crc16(&src);
+        src.extend_from_slice(&crc.to_le_bytes());
+        let adu = codec.decode(&mut src).unwrap().unwrap();
+        assert!(matches!(
+            adu.response,
+            Response::ReadDeviceIdentification(ref id) if id.objects.len() == 2
+        ));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn decode_bad_crc() {
+        let mut codec = ClientCodec::default();
+        let mut src = BytesMut::from(&[0x01, 0x06, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00][..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(stream in proptest::collection::vec(any::<u8>(), 0..600)) {
+            let mut codec = ClientCodec::default();
+            let mut src = BytesMut::from(&stream[..]);
+            while let Ok(Some(_)) = codec.decode(&mut src) {}
+        }
+
+        #[test]
+        fn decode_with_valid_crc_never_panics(frame in proptest::collection::vec(any::<u8>(), 2..300)) {
+            let mut src = BytesMut::from(&frame[..]);
+            let crc = crc16(&src);
+            src.extend_from_slice(&crc.to_le_bytes());
+            let _ = ClientCodec::default().decode(&mut src);
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use std::io::{Error, ErrorKind};
+use std::io::Error;
 
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{BufMut, Bytes};
 use tokio_util::codec::{Decoder, Encoder};
 
-use super::{Request, Response};
+use super::{ProtocolError, Request, Response, MAX_PDU_LEN};
 
 const HEADER_LEN: usize = 7;
 const PROTOCOL_ID: u16 = 0x0000;
@@ -47,20 +47,24 @@ impl Decoder for AduDecoder {
             return Ok(None);
         }
 
-        let len = BigEndian::read_u16(&src[4..6]);
-        if src.len() < HEADER_LEN + len as usize - 1 {
-            return Ok(None);
-        }
-
         let protocol_id = BigEndian::read_u16(&src[2..4]);
         if protocol_id != PROTOCOL_ID {
-            return Err(Error::new(ErrorKind::InvalidData, "invalid protocol id"));
+            return Err(ProtocolError::InvalidProtocolId(protocol_id).into());
+        }
+
+        // The length field counts the unit id and the PDU.
+        let len = BigEndian::read_u16(&src[4..6]) as usize;
+        if !(2..=MAX_PDU_LEN + 1).contains(&len) {
+            return Err(ProtocolError::InvalidLength(len).into());
+        }
+        if src.len() < HEADER_LEN + len - 1 {
+            return Ok(None);
         }
 
         let transaction_id = BigEndian::read_u16(&src[0..2]);
         let unit_id = src[6];
         let _header = src.split_to(HEADER_LEN);
-        let pdu_data = src.split_to(len as usize - 1).freeze();
+        let pdu_data = src.split_to(len - 1).freeze();
 
         let header = Header {
             transaction_id,
@@ -94,7 +98,7 @@ impl<'a> Encoder<RequestAdu<'a>> for ClientCodec {
         dst: &mut bytes::BytesMut,
     ) -> Result<(), Self::Error> {
         let RequestAdu { header, request } = item;
-        let pdu_data: Bytes = request.into();
+        let pdu_data = Bytes::try_from(request)?;
         dst.reserve(pdu_data.len() + 7);
         dst.put_u16(header.transaction_id);
         dst.put_u16(PROTOCOL_ID);
@@ -104,3 +108,50 @@ impl<'a> Encoder<RequestAdu<'a>> for ClientCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use proptest::prelude::*;
+    use tokio_util::codec::Decoder;
+
+    use super::super::ProtocolError;
+    use super::ClientCodec;
+
+    fn decode_err(frame: &[u8]) -> ProtocolError {
+        let err = ClientCodec::default()
+            .decode(&mut BytesMut::from(frame))
+            .err()
+            .unwrap();
+        err.into_inner()
+            .unwrap()
+            .downcast::<ProtocolError>()
+            .map(|err| *err)
+            .unwrap()
+    }
+
+    #[test]
+    fn invalid_length() {
+        assert_eq!(
+            decode_err(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            ProtocolError::InvalidLength(0)
+        );
+        assert_eq!(
+            decode_err(&[0x00, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0x01]),
+            ProtocolError::InvalidLength(0xFFFF)
+        );
+        assert_eq!(
+            decode_err(&[0x00, 0x01, 0x00, 0x01, 0x00, 0x06, 0x01]),
+            ProtocolError::InvalidProtocolId(1)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(stream in proptest::collection::vec(any::<u8>(), 0..600)) {
+            let mut codec = ClientCodec::default();
+            let mut src = BytesMut::from(&stream[..]);
+            while let Ok(Some(_)) = codec.decode(&mut src) {}
+        }
+    }
+}
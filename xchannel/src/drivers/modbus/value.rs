@@ -0,0 +1,293 @@
+use crate::error::*;
+use crate::module::value::{Value, ValueType};
+
+use super::{Address, Order};
+
+impl Order {
+    // Lays the registers out as bytes with the most significant one first.
+    fn to_bytes(self, regs: &[u16]) -> Vec<u8> {
+        let swap_words = matches!(self, Order::CDAB | Order::DCBA);
+        let swap_bytes = matches!(self, Order::BADC | Order::DCBA);
+
+        let mut bytes = Vec::with_capacity(regs.len() * 2);
+        let mut push = |reg: &u16| {
+            let [hi, lo] = reg.to_be_bytes();
+            if swap_bytes {
+                bytes.extend_from_slice(&[lo, hi]);
+            } else {
+                bytes.extend_from_slice(&[hi, lo]);
+            }
+        };
+
+        if swap_words {
+            regs.iter().rev().for_each(&mut push);
+        } else {
+            regs.iter().for_each(&mut push);
+        }
+
+        bytes
+    }
+
+    // Inverse of `to_bytes`: registers holding bytes given most significant
+    // first.
+    fn to_registers(self, bytes: &[u8]) -> Vec<u16> {
+        let regs: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect();
+        // both transformations are their own inverse
+        let bytes = self.to_bytes(&regs);
+        bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+}
+
+fn short_read() -> XError {
+    XError::new(XErrorKind::TagError, "not enough data for the tag")
+}
+
+fn unsupported(vtype: ValueType) -> XError {
+    XError::new(
+        XErrorKind::TagError,
+        &format!("unsupport {vtype:?} for the Modbus area"),
+    )
+}
+
+impl Address {
+    // Value of a coil or discrete input tag, `bits` starts at the tag address.
+    pub fn decode_bits(&self, vtype: ValueType, bits: &[bool]) -> XResult<Value> {
+        let bit = *bits.first().ok_or_else(short_read)?;
+
+        match vtype {
+            ValueType::BIT => Ok(Value::BIT(u8::from(bit))),
+            ValueType::BOOL => Ok(Value::BOOL(bit)),
+            _ => Err(unsupported(vtype)),
+        }
+    }
+
+    // Value of a register tag, `regs` starts at the tag address.
+    pub fn decode_registers(&self, vtype: ValueType, regs: &[u16]) -> XResult<Value> {
+        let regs = regs.get(..self.quantity as usize).ok_or_else(short_read)?;
+        let bytes = self.order.to_bytes(regs);
+
+        let value = match vtype {
+            ValueType::BIT => Value::BIT(((regs[0] >> self.bit) & 0x01) as u8),
+            ValueType::BOOL => Value::BOOL((regs[0] >> self.bit) & 0x01 > 0),
+            ValueType::UINT8 => Value::UINT8((regs[0] >> self.bit) as u8),
+            ValueType::INT8 => Value::INT8((regs[0] >> self.bit) as u8 as i8),
+            ValueType::UINT16 => Value::UINT16(u16::from_be_bytes([bytes[0], bytes[1]])),
+            ValueType::INT16 => Value::INT16(i16::from_be_bytes([bytes[0], bytes[1]])),
+            ValueType::UINT32 => Value::UINT32(u32::from_be_bytes(word32(&bytes)?)),
+            ValueType::INT32 => Value::INT32(i32::from_be_bytes(word32(&bytes)?)),
+            ValueType::FLOAT => Value::FLOAT(f32::from_be_bytes(word32(&bytes)?)),
+            ValueType::UINT64 => Value::UINT64(u64::from_be_bytes(word64(&bytes)?)),
+            ValueType::INT64 => Value::INT64(i64::from_be_bytes(word64(&bytes)?)),
+            ValueType::DOUBLE => Value::DOUBLE(f64::from_be_bytes(word64(&bytes)?)),
+            ValueType::STRING => {
+                // the string ends at the first NUL or after `length` bytes
+                let bytes = &bytes[..(self.length as usize).min(bytes.len())];
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                Value::STRING {
+                    length: Some(self.length),
+                    str: Some(String::from_utf8_lossy(&bytes[..end]).into_owned()),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+impl Address {
+    // Registers holding the value, for tags that occupy whole registers.
+    pub fn encode_registers(&self, value: &Value) -> XResult<Vec<u16>> {
+        let bytes = match value {
+            Value::UINT16(v) => v.to_be_bytes().to_vec(),
+            Value::INT16(v) => v.to_be_bytes().to_vec(),
+            Value::UINT32(v) => v.to_be_bytes().to_vec(),
+            Value::INT32(v) => v.to_be_bytes().to_vec(),
+            Value::FLOAT(v) => v.to_be_bytes().to_vec(),
+            Value::UINT64(v) => v.to_be_bytes().to_vec(),
+            Value::INT64(v) => v.to_be_bytes().to_vec(),
+            Value::DOUBLE(v) => v.to_be_bytes().to_vec(),
+            Value::STRING { str, .. } => {
+                let str = str.as_deref().unwrap_or_default();
+                if str.len() > self.length as usize {
+                    return Err(XError::new(
+                        XErrorKind::TagError,
+                        &format!("string longer than {} bytes", self.length),
+                    ));
+                }
+                // padded with NUL up to the registers of the tag
+                let mut bytes = str.as_bytes().to_vec();
+                bytes.resize(self.quantity as usize * 2, 0);
+                bytes
+            }
+            _ => return Err(unsupported(value.v_type())),
+        };
+
+        Ok(self.order.to_registers(&bytes))
+    }
+
+    // AND and OR masks of a Mask Write Register (0x16) for tags that occupy
+    // part of a register: result = (current AND and_mask) OR (or_mask AND NOT and_mask).
+    pub fn encode_mask(&self, value: &Value) -> XResult<(u16, u16)> {
+        let (width, bits) = match value {
+            Value::BIT(v) => (0x01, u16::from(*v & 0x01)),
+            Value::BOOL(v) => (0x01, u16::from(*v)),
+            Value::UINT8(v) => (0xFF, u16::from(*v)),
+            Value::INT8(v) => (0xFF, u16::from(*v as u8)),
+            _ => return Err(unsupported(value.v_type())),
+        };
+
+        Ok((!(width << self.bit), bits << self.bit))
+    }
+}
+
+fn word32(bytes: &[u8]) -> XResult<[u8; 4]> {
+    bytes
+        .get(..4)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(short_read)
+}
+
+fn word64(bytes: &[u8]) -> XResult<[u8; 8]> {
+    bytes
+        .get(..8)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(short_read)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::XResult;
+    use crate::module::driver::Tag;
+    use crate::module::value::{DataType, Value};
+
+    use super::Address;
+
+    fn decode(dtype: DataType, address: &str, regs: &[u16]) -> Value {
+        let tag = Tag {
+            name: "test".to_string(),
+            value: dtype.default_value(),
+            dtype,
+            address: address.to_string(),
+        };
+        let address: XResult<Address> = (&tag).try_into();
+        address
+            .unwrap()
+            .decode_registers(tag.value.v_type(), regs)
+            .unwrap()
+    }
+
+    #[test]
+    fn word_order() {
+        let regs = [0x4049, 0x0FDB];
+        assert_eq!(
+            decode(DataType::DWORD, "1.41", &regs),
+            Value::UINT32(0x4049_0FDB)
+        );
+        assert_eq!(
+            decode(DataType::DWORD, "1.41.CDAB", &regs),
+            Value::UINT32(0x0FDB_4049)
+        );
+        assert_eq!(
+            decode(DataType::DWORD, "1.41.BADC", &regs),
+            Value::UINT32(0x4940_DB0F)
+        );
+        assert_eq!(
+            decode(DataType::DWORD, "1.41.DCBA", &regs),
+            Value::UINT32(0xDB0F_4940)
+        );
+        assert_eq!(
+            decode(DataType::FLOAT, "1.41", &regs),
+            Value::FLOAT(f32::from_bits(0x4049_0FDB))
+        );
+
+        let regs = [0x0102, 0x0304, 0x0506, 0x0708];
+        assert_eq!(
+            decode(DataType::LWORD, "1.41.CDAB", &regs),
+            Value::UINT64(0x0708_0506_0304_0102)
+        );
+        assert_eq!(
+            decode(DataType::LINT, "1.41.DCBA", &regs),
+            Value::INT64(0x0807_0605_0403_0201)
+        );
+    }
+
+    #[test]
+    fn bits_and_bytes() {
+        assert_eq!(
+            decode(DataType::BOOL, "1.41.15", &[0x8000]),
+            Value::BOOL(true)
+        );
+        assert_eq!(decode(DataType::BIT, "1.41.14", &[0x8000]), Value::BIT(0));
+        assert_eq!(
+            decode(DataType::BYTE, "1.41", &[0x12FE]),
+            Value::UINT8(0xFE)
+        );
+        assert_eq!(decode(DataType::SINT, "1.41.H", &[0xFE12]), Value::INT8(-2));
+        assert_eq!(decode(DataType::INT, "1.41", &[0xFFFE]), Value::INT16(-2));
+    }
+
+    fn encode(dtype: DataType, address: &str, value: &Value) -> Vec<u16> {
+        let tag = Tag {
+            name: "test".to_string(),
+            value: dtype.default_value(),
+            dtype,
+            address: address.to_string(),
+        };
+        let address: XResult<Address> = (&tag).try_into();
+        address.unwrap().encode_registers(value).unwrap()
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        for (dtype, address, value) in [
+            (DataType::DWORD, "1.41.CDAB", Value::UINT32(0x4049_0FDB)),
+            (DataType::FLOAT, "1.41.BADC", Value::FLOAT(3.25)),
+            (DataType::LINT, "1.41.DCBA", Value::INT64(-5)),
+            (DataType::DOUBLE, "1.41", Value::DOUBLE(-1.5)),
+            (DataType::INT, "1.41", Value::INT16(-2)),
+        ] {
+            let regs = encode(dtype, address, &value);
+            assert_eq!(decode(dtype, address, &regs), value);
+        }
+        assert_eq!(
+            encode(DataType::DWORD, "1.41.CDAB", &Value::UINT32(0x0102_0304)),
+            [0x0304, 0x0102]
+        );
+        assert_eq!(
+            encode(
+                DataType::STRING,
+                "1.41.5L",
+                &Value::STRING {
+                    length: None,
+                    str: Some("ABC".to_string()),
+                }
+            ),
+            [0x4241, 0x0043, 0x0000]
+        );
+    }
+
+    #[test]
+    fn string() {
+        let regs = [0x4142, 0x4300];
+        assert_eq!(
+            decode(DataType::STRING, "1.41.4", &regs),
+            Value::STRING {
+                length: Some(4),
+                str: Some("ABC".to_string()),
+            }
+        );
+        assert_eq!(
+            decode(DataType::STRING, "1.41.3L", &[0x4241, 0x0043]),
+            Value::STRING {
+                length: Some(3),
+                str: Some("ABC".to_string()),
+            }
+        );
+    }
+}
@@ -14,6 +14,8 @@ pub enum XError {
     TagError(i32, String), // 1004
     #[error("Parameter Error: {0}")]
     ParameterError(String), // 1005
+    #[error("Alarm Error: {0}")]
+    AlarmError(String), // 1006
     #[error("DB Error: {0}")]
     DBError(String), // 1101
     #[error("Other Error: {0}")]
@@ -27,6 +29,7 @@ pub enum XErrorKind {
     TableError,
     TagError,
     ParameterError,
+    AlarmError,
     DBError,
     IOError,
 }
@@ -42,6 +45,7 @@ impl XError {
             XErrorKind::TableError => TableError(msg.to_string()),
             XErrorKind::TagError => TagError(-1, msg.to_string()),
             XErrorKind::ParameterError => ParameterError(msg.to_string()),
+            XErrorKind::AlarmError => AlarmError(msg.to_string()),
             XErrorKind::DBError => DBError(msg.to_string()),
             XErrorKind::IOError => IOError(msg.to_string()),
         }
@@ -55,6 +59,7 @@ impl XError {
             TableError(_) => 1003,
             TagError(_, _) => 1004,
             ParameterError(_) => 1005,
+            AlarmError(_) => 1006,
             DBError(_) => 1101,
             IOError(_) => 1201,
         }
@@ -88,6 +93,7 @@ impl XError {
             TableError(_) => XErrorKind::TableError,
             TagError(_, _) => XErrorKind::TagError,
             ParameterError(_) => XErrorKind::ParameterError,
+            AlarmError(_) => XErrorKind::AlarmError,
             DBError(_) => XErrorKind::DBError,
             IOError(_) => XErrorKind::IOError,
         }
@@ -0,0 +1,370 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::*;
+
+use super::tag::{Quality, TagValue};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Condition {
+    HighHigh(f64),
+    High(f64),
+    Low(f64),
+    LowLow(f64),
+    // change per second, either direction
+    RateOfChange(f64),
+    // active while the tag equals the value
+    Boolean(bool),
+    // active while the tag is one of the states
+    Discrete(Vec<i64>),
+}
+
+// An alarm on one tag. The deadband is the distance a limit alarm must move
+// back past its limit to clear; the delays are how long, in milliseconds, the
+// condition must hold or be gone before the alarm changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmDef {
+    pub name: String,
+    pub device: String,
+    pub table: String,
+    pub tag: String,
+    pub condition: Condition,
+    #[serde(default)]
+    pub deadband: f64,
+    #[serde(default)]
+    pub on_delay: u64,
+    #[serde(default)]
+    pub off_delay: u64,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum AlarmState {
+    Normal,
+    ActiveUnacknowledged,
+    ActiveAcknowledged,
+    ClearedUnacknowledged,
+}
+
+// A state change, as kept in the history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmEvent {
+    pub name: String,
+    pub device: String,
+    pub table: String,
+    pub tag: String,
+    pub state: AlarmState,
+    pub value: Option<f64>,
+    pub message: Option<String>,
+    pub time: DateTime<Utc>,
+    // milliseconds since the epoch, for range queries
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmInfo {
+    #[serde(flatten)]
+    pub def: AlarmDef,
+    pub state: AlarmState,
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct Alarm {
+    def: AlarmDef,
+    state: AlarmState,
+    since: Option<DateTime<Utc>>,
+    // condition after delays, and when the raw condition last started to differ
+    active: bool,
+    pending: Option<Instant>,
+    last: Option<(f64, Instant)>,
+    value: Option<f64>,
+}
+
+impl AlarmDef {
+    pub fn check(&self) -> XResult<()> {
+        let limit = match &self.condition {
+            Condition::HighHigh(limit)
+            | Condition::High(limit)
+            | Condition::Low(limit)
+            | Condition::LowLow(limit) => limit.is_finite(),
+            Condition::RateOfChange(limit) => limit.is_finite() && *limit > 0.0,
+            Condition::Boolean(_) | Condition::Discrete(_) => true,
+        };
+
+        if limit && self.deadband.is_finite() && self.deadband >= 0.0 {
+            Ok(())
+        } else {
+            Err(XError::new(
+                XErrorKind::AlarmError,
+                &format!("invalid alarm {}", self.name),
+            ))
+        }
+    }
+
+    fn event(&self, state: AlarmState, value: Option<f64>, time: DateTime<Utc>) -> AlarmEvent {
+        AlarmEvent {
+            name: self.name.clone(),
+            device: self.device.clone(),
+            table: self.table.clone(),
+            tag: self.tag.clone(),
+            state,
+            value,
+            message: self.message.clone(),
+            time,
+            timestamp: time.timestamp_millis(),
+        }
+    }
+}
+
+impl Alarm {
+    pub fn new(def: AlarmDef) -> Self {
+        Alarm {
+            def,
+            state: AlarmState::Normal,
+            since: None,
+            active: false,
+            pending: None,
+            last: None,
+            value: None,
+        }
+    }
+
+    pub fn def(&self) -> &AlarmDef {
+        &self.def
+    }
+
+    // The device was renamed.
+    pub fn set_device(&mut self, device: &str) {
+        self.def.device = device.to_string();
+    }
+
+    pub fn state(&self) -> AlarmState {
+        self.state
+    }
+
+    pub fn info(&self) -> AlarmInfo {
+        AlarmInfo {
+            def: self.def.clone(),
+            state: self.state,
+            since: self.since,
+        }
+    }
+
+    fn condition(&self, v: f64, now: Instant) -> bool {
+        // an active limit alarm holds until the value is back past the deadband
+        let band = if self.active { self.def.deadband } else { 0.0 };
+
+        match &self.def.condition {
+            Condition::HighHigh(limit) | Condition::High(limit) => v > limit - band,
+            Condition::Low(limit) | Condition::LowLow(limit) => v < limit + band,
+            Condition::RateOfChange(limit) => match self.last {
+                Some((last, at)) if now > at => {
+                    (v - last).abs() / now.duration_since(at).as_secs_f64() > limit - band
+                }
+                // no rate until the second sample
+                _ => self.active,
+            },
+            Condition::Boolean(when) => (v != 0.0) == *when,
+            Condition::Discrete(states) => states.iter().any(|s| *s as f64 == v),
+        }
+    }
+
+    // Feeds a new value of the tag; returns the event when the state changes.
+    // Values without good quality leave the alarm as it is, and a pending
+    // change waits for the next good one.
+    pub fn update(&mut self, value: &TagValue, now: Instant) -> Option<AlarmEvent> {
+        let v = match (value.quality, value.value.as_f64()) {
+            (Quality::Good, Some(v)) => v,
+            _ => {
+                self.last = None;
+                self.pending = None;
+                return None;
+            }
+        };
+
+        let raw = self.condition(v, now);
+        self.last = Some((v, now));
+        self.value = Some(v);
+
+        if raw == self.active {
+            self.pending = None;
+            return None;
+        }
+
+        self.pending.get_or_insert(now);
+        self.expire(now)
+    }
+
+    // When the pending change takes effect unless the value changes before.
+    pub fn deadline(&self) -> Option<Instant> {
+        let delay = if self.active {
+            self.def.off_delay
+        } else {
+            self.def.on_delay
+        };
+        self.pending
+            .map(|started| started + Duration::from_millis(delay))
+    }
+
+    // Completes the pending change once its delay is over; the tag keeps its
+    // last value until a new one arrives, so the condition still holds.
+    pub fn expire(&mut self, now: Instant) -> Option<AlarmEvent> {
+        self.deadline().filter(|deadline| now >= *deadline)?;
+
+        let raw = !self.active;
+        self.active = raw;
+        self.pending = None;
+        let state = match (raw, self.state) {
+            (true, _) => AlarmState::ActiveUnacknowledged,
+            (false, AlarmState::ActiveAcknowledged) => AlarmState::Normal,
+            (false, _) => AlarmState::ClearedUnacknowledged,
+        };
+        self.transition(state)
+    }
+
+    pub fn acknowledge(&mut self) -> XResult<AlarmEvent> {
+        let state = match self.state {
+            AlarmState::ActiveUnacknowledged => AlarmState::ActiveAcknowledged,
+            AlarmState::ClearedUnacknowledged => AlarmState::Normal,
+            _ => {
+                return Err(XError::new(
+                    XErrorKind::AlarmError,
+                    &format!("{} is not waiting for acknowledgement", self.def.name),
+                ))
+            }
+        };
+
+        Ok(self.transition(state).expect("state changes"))
+    }
+
+    fn transition(&mut self, state: AlarmState) -> Option<AlarmEvent> {
+        if state == self.state {
+            return None;
+        }
+
+        let time = Utc::now();
+        self.state = state;
+        self.since = Some(time);
+        Some(self.def.event(state, self.value, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use chrono::Utc;
+
+    use crate::module::tag::{QualityReason, TagValue};
+    use crate::module::value::Value;
+
+    use super::{Alarm, AlarmDef, AlarmState, Condition};
+
+    fn alarm(condition: Condition, deadband: f64, on_delay: u64) -> Alarm {
+        Alarm::new(AlarmDef {
+            name: "alarm".to_string(),
+            device: "device".to_string(),
+            table: "table".to_string(),
+            tag: "tag".to_string(),
+            condition,
+            deadband,
+            on_delay,
+            off_delay: 0,
+            message: None,
+        })
+    }
+
+    fn value(v: f64) -> TagValue {
+        TagValue::good(Value::DOUBLE(v), Utc::now())
+    }
+
+    #[test]
+    fn high_with_deadband_and_ack() {
+        let mut alarm = alarm(Condition::High(100.0), 5.0, 0);
+        let now = Instant::now();
+
+        assert!(alarm.update(&value(100.0), now).is_none());
+        let event = alarm.update(&value(101.0), now).unwrap();
+        assert_eq!(event.state, AlarmState::ActiveUnacknowledged);
+        assert_eq!(event.value, Some(101.0));
+
+        // inside the deadband, still active
+        assert!(alarm.update(&value(96.0), now).is_none());
+        assert_eq!(
+            alarm.acknowledge().unwrap().state,
+            AlarmState::ActiveAcknowledged
+        );
+        assert!(alarm.acknowledge().is_err());
+
+        // a bad value does not clear it
+        let bad = TagValue::bad(Value::DOUBLE(0.0), QualityReason::CommFailure);
+        assert!(alarm.update(&bad, now).is_none());
+        assert_eq!(
+            alarm.update(&value(94.0), now).unwrap().state,
+            AlarmState::Normal
+        );
+
+        alarm.update(&value(120.0), now);
+        assert_eq!(
+            alarm.update(&value(0.0), now).unwrap().state,
+            AlarmState::ClearedUnacknowledged
+        );
+        assert_eq!(alarm.acknowledge().unwrap().state, AlarmState::Normal);
+    }
+
+    #[test]
+    fn on_delay() {
+        let mut alarm = alarm(Condition::Boolean(true), 0.0, 1000);
+        let now = Instant::now();
+        let at = |ms: u64| now + Duration::from_millis(ms);
+
+        assert!(alarm.update(&value(1.0), now).is_none());
+        assert!(alarm.update(&value(1.0), at(500)).is_none());
+        // dropped out, the delay starts over
+        assert!(alarm.update(&value(0.0), at(600)).is_none());
+        assert!(alarm.update(&value(1.0), at(700)).is_none());
+        assert!(alarm.update(&value(1.0), at(1500)).is_none());
+        assert_eq!(
+            alarm.update(&value(1.0), at(1700)).unwrap().state,
+            AlarmState::ActiveUnacknowledged
+        );
+    }
+
+    #[test]
+    fn constant_value() {
+        let mut alarm = alarm(Condition::High(100.0), 0.0, 1000);
+        let now = Instant::now();
+        let at = |ms: u64| now + Duration::from_millis(ms);
+
+        // the value never changes again after it goes over the limit
+        assert!(alarm.update(&value(120.0), now).is_none());
+        assert_eq!(alarm.deadline(), Some(at(1000)));
+        assert!(alarm.expire(at(999)).is_none());
+        assert_eq!(
+            alarm.expire(at(1000)).unwrap().state,
+            AlarmState::ActiveUnacknowledged
+        );
+        assert_eq!(alarm.deadline(), None);
+        assert!(alarm.expire(at(2000)).is_none());
+    }
+
+    #[test]
+    fn rate_and_discrete() {
+        let mut roc = alarm(Condition::RateOfChange(10.0), 0.0, 0);
+        let now = Instant::now();
+        assert!(roc.update(&value(0.0), now).is_none());
+        assert!(roc
+            .update(&value(5.0), now + Duration::from_secs(1))
+            .is_none());
+        assert!(roc
+            .update(&value(30.0), now + Duration::from_secs(2))
+            .is_some());
+
+        let mut discrete = alarm(Condition::Discrete(vec![3, 4]), 0.0, 0);
+        assert!(discrete.update(&value(2.0), now).is_none());
+        assert!(discrete.update(&value(4.0), now).is_some());
+    }
+}
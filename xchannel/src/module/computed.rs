@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+
+use crate::error::*;
+
+use super::expr::Expr;
+use super::tag::{Quality, QualityReason, TagValue};
+use super::value::DataType;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TagRef {
+    pub device: String,
+    pub table: String,
+    pub tag: String,
+}
+
+impl TagRef {
+    pub fn new(device: &str, table: &str, tag: &str) -> Self {
+        TagRef {
+            device: device.to_string(),
+            table: table.to_string(),
+            tag: tag.to_string(),
+        }
+    }
+
+    // Tag a path of an expression points to, relative to the computed tag.
+    fn resolve(&self, path: &[String]) -> Self {
+        match path {
+            [tag] => TagRef::new(&self.device, &self.table, tag),
+            [table, tag] => TagRef::new(&self.device, table, tag),
+            [device, table, tag, ..] => TagRef::new(device, table, tag),
+            [] => self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Formula {
+    expr: Expr,
+    inputs: Vec<TagRef>,
+}
+
+impl Formula {
+    pub fn new(target: &TagRef, src: &str) -> XResult<Self> {
+        let expr = Expr::parse(src)?;
+        let inputs = expr
+            .refs()
+            .iter()
+            .map(|path| target.resolve(path))
+            .collect();
+
+        Ok(Formula { expr, inputs })
+    }
+
+    pub fn inputs(&self) -> &[TagRef] {
+        &self.inputs
+    }
+
+    // Result as a `dtype` record. Bad inputs make the result bad, uncertain
+    // ones make it uncertain; it is as recent as the newest input.
+    pub fn eval(&self, dtype: DataType, lookup: impl Fn(&TagRef) -> Option<TagValue>) -> TagValue {
+        let placeholder = dtype.default_value();
+        let mut args = Vec::with_capacity(self.inputs.len());
+        let mut uncertain = None;
+        let mut source_time = None;
+
+        for input in &self.inputs {
+            let Some(value) = lookup(input) else {
+                return TagValue::bad(placeholder, QualityReason::ConfigError);
+            };
+            match value.quality {
+                Quality::Bad => {
+                    let reason = value.reason.unwrap_or(QualityReason::ConfigError);
+                    return TagValue::bad(placeholder, reason);
+                }
+                Quality::Uncertain => uncertain = uncertain.or(value.reason),
+                Quality::Good => {}
+            }
+            let Some(arg) = value.value.as_f64() else {
+                return TagValue::bad(placeholder, QualityReason::ConfigError);
+            };
+
+            args.push(arg);
+            source_time = source_time.max(value.source_time);
+        }
+
+        let Ok(value) = dtype.cast(self.expr.eval(&args)) else {
+            return TagValue::bad(placeholder, QualityReason::ConfigError);
+        };
+
+        let mut value = TagValue::good(value, source_time.unwrap_or_else(Utc::now));
+        if uncertain.is_some() {
+            value.quality = Quality::Uncertain;
+            value.reason = uncertain;
+        }
+        value
+    }
+}
+
+// Computed tags and the tags they read, kept free of cycles.
+#[derive(Debug, Clone, Default)]
+pub struct Computed {
+    formulas: HashMap<TagRef, Formula>,
+}
+
+impl Computed {
+    pub fn get(&self, target: &TagRef) -> Option<&Formula> {
+        self.formulas.get(target)
+    }
+
+    pub fn insert(&mut self, target: TagRef, formula: Formula) -> XResult<()> {
+        // the new tag closes a cycle if it is reachable from its own inputs
+        let mut pending: Vec<&TagRef> = formula.inputs.iter().collect();
+        let mut seen = HashSet::new();
+        while let Some(input) = pending.pop() {
+            if *input == target {
+                return Err(XError::new(
+                    XErrorKind::TagError,
+                    &format!("{} depends on itself", target.tag),
+                ));
+            }
+            if seen.insert(input) {
+                if let Some(formula) = self.formulas.get(input) {
+                    pending.extend(formula.inputs.iter());
+                }
+            }
+        }
+
+        self.formulas.insert(target, formula);
+        Ok(())
+    }
+
+    pub fn retain(&mut self, keep: impl Fn(&TagRef) -> bool) {
+        self.formulas.retain(|target, _| keep(target));
+    }
+
+    pub fn targets(&self) -> Vec<TagRef> {
+        self.formulas.keys().cloned().collect()
+    }
+
+    // Computed tags reading any input matching `reads`.
+    pub fn dependents(&self, reads: impl Fn(&TagRef) -> bool) -> Vec<TagRef> {
+        self.formulas
+            .iter()
+            .filter(|(_, formula)| formula.inputs.iter().any(&reads))
+            .map(|(target, _)| target.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::module::tag::{Quality, QualityReason, TagValue};
+    use crate::module::value::{DataType, Value};
+
+    use super::{Computed, Formula, TagRef};
+
+    #[test]
+    fn resolve_and_eval() {
+        let target = TagRef::new("plc", "tanks", "total");
+        let formula = Formula::new(&target, "level * 0.5 + pumps.flow + other.t.x").unwrap();
+        assert_eq!(
+            formula.inputs(),
+            [
+                TagRef::new("plc", "tanks", "level"),
+                TagRef::new("plc", "pumps", "flow"),
+                TagRef::new("other", "t", "x"),
+            ]
+        );
+
+        let good = |v: i16| Some(TagValue::good(Value::INT16(v), Utc::now()));
+        let value = formula.eval(DataType::LReal, |input| match input.tag.as_str() {
+            "level" => good(10),
+            "flow" => good(2),
+            _ => good(1),
+        });
+        assert_eq!(value.value, Value::DOUBLE(8.0));
+        assert_eq!(value.quality, Quality::Good);
+
+        let value = formula.eval(DataType::LReal, |input| match input.tag.as_str() {
+            "flow" => Some(TagValue::bad(Value::INT16(0), QualityReason::CommFailure)),
+            _ => good(1),
+        });
+        assert_eq!(value.reason, Some(QualityReason::CommFailure));
+
+        let value = formula.eval(DataType::LReal, |_| None);
+        assert_eq!(value.reason, Some(QualityReason::ConfigError));
+    }
+
+    #[test]
+    fn cycles() {
+        let a = TagRef::new("d", "t", "a");
+        let b = TagRef::new("d", "t", "b");
+        let c = TagRef::new("d", "t", "c");
+        let mut computed = Computed::default();
+
+        computed
+            .insert(b.clone(), Formula::new(&b, "a + 1").unwrap())
+            .unwrap();
+        computed
+            .insert(c.clone(), Formula::new(&c, "b * 2").unwrap())
+            .unwrap();
+        assert!(computed
+            .insert(a.clone(), Formula::new(&a, "c").unwrap())
+            .is_err());
+        assert!(computed
+            .insert(a.clone(), Formula::new(&a, "a").unwrap())
+            .is_err());
+        assert_eq!(computed.dependents(|input| *input == a), [b]);
+    }
+}
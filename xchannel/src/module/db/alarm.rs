@@ -0,0 +1,77 @@
+use tracing::trace;
+
+use crate::error::*;
+
+use super::super::alarm::{AlarmDef, AlarmEvent};
+use super::DBLayer;
+use super::Record;
+
+pub struct Alarm;
+
+impl Alarm {
+    pub(super) const TABLE_NAME: &'static str = "alarm";
+    pub(super) const HISTORY_TABLE_NAME: &'static str = "alarm_history";
+
+    pub async fn select(db: &DBLayer) -> XResult<Vec<AlarmDef>> {
+        let re = db.db.select(Self::TABLE_NAME).await?;
+        trace!("load {:?}", re);
+        Ok(re)
+    }
+
+    pub async fn add(db: &DBLayer, alarm: &AlarmDef) -> XResult<()> {
+        let re: Vec<Record> = db.db.create(Self::TABLE_NAME).content(alarm).await?;
+        trace!("store alarm {:?}", re);
+        Ok(())
+    }
+
+    pub async fn delete(db: &DBLayer, name: &str) -> XResult<()> {
+        let re = db
+            .db
+            .query("DELETE type::table($table) WHERE name = $value")
+            .bind(("table", Self::TABLE_NAME))
+            .bind(("value", name))
+            .await?
+            .check()?;
+        trace!("delete response {:?}", re);
+        Ok(())
+    }
+
+    pub async fn record(db: &DBLayer, event: &AlarmEvent) -> XResult<()> {
+        let re: Vec<Record> = db
+            .db
+            .create(Self::HISTORY_TABLE_NAME)
+            .content(event)
+            .await?;
+        trace!("store alarm event {:?}", re);
+        Ok(())
+    }
+
+    // State changes between the timestamps, in milliseconds, newest first.
+    pub async fn history(
+        db: &DBLayer,
+        name: Option<&str>,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> XResult<Vec<AlarmEvent>> {
+        let filter = if name.is_some() {
+            "AND name = $name"
+        } else {
+            ""
+        };
+        let mut re = db
+            .db
+            .query(format!(
+                "SELECT * FROM type::table($table) WHERE timestamp >= $from AND timestamp <= $to {filter} ORDER BY timestamp DESC LIMIT $limit"
+            ))
+            .bind(("table", Self::HISTORY_TABLE_NAME))
+            .bind(("from", from))
+            .bind(("to", to))
+            .bind(("name", name))
+            .bind(("limit", limit))
+            .await?
+            .check()?;
+        trace!("load {:?}", re);
+        Ok(re.take(0)?)
+    }
+}
@@ -4,7 +4,10 @@ use tracing::trace;
 use crate::error::*;
 
 use super::super::driver::Setting;
+use super::alarm::Alarm;
+use super::history::History;
 use super::table::Table;
+use super::tag::Tag;
 use super::DBLayer;
 use super::Record;
 
@@ -16,6 +19,12 @@ pub struct Device {
     pub name: String,
     pub driver: String,
     pub setting: Option<Setting>,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
 }
 
 impl Device {
@@ -37,6 +46,65 @@ impl Device {
         }
     }
 
+    pub async fn set_setting(db: &DBLayer, name: &str, setting: Option<&Setting>) -> XResult<()> {
+        let re = db
+            .db
+            .query("UPDATE type::table($table) SET setting = $setting WHERE name = $value")
+            .bind(("table", Self::TABLE_NAME))
+            .bind(("setting", setting))
+            .bind(("value", name))
+            .await?
+            .check()?;
+        trace!("update response {:?}", re);
+        Ok(())
+    }
+
+    pub async fn set_enabled(db: &DBLayer, name: &str, enabled: bool) -> XResult<()> {
+        let re = db
+            .db
+            .query("UPDATE type::table($table) SET enabled = $enabled WHERE name = $value")
+            .bind(("table", Self::TABLE_NAME))
+            .bind(("enabled", enabled))
+            .bind(("value", name))
+            .await?
+            .check()?;
+        trace!("update response {:?}", re);
+        Ok(())
+    }
+
+    // Renames the device along with its tables and recorded history; the tags
+    // move to the tag table of the new name. All or nothing.
+    pub async fn rename(db: &DBLayer, name: &str, new_name: &str) -> XResult<()> {
+        let re = db
+            .db
+            .query(
+                "BEGIN TRANSACTION;
+                UPDATE type::table($table) SET name = $name WHERE name = $value;
+                UPDATE type::table($tables) SET device = $name WHERE device = $value;
+                FOR $tag IN (SELECT * OMIT id FROM type::table($old_tags)) {
+                    CREATE type::table($new_tags) CONTENT $tag;
+                };
+                DELETE type::table($old_tags);
+                UPDATE type::table($history) SET device = $name WHERE device = $value;
+                UPDATE type::table($alarms) SET device = $name WHERE device = $value;
+                UPDATE type::table($alarm_history) SET device = $name WHERE device = $value;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("table", Self::TABLE_NAME))
+            .bind(("tables", Table::TABLE_NAME))
+            .bind(("history", History::TABLE_NAME))
+            .bind(("alarms", Alarm::TABLE_NAME))
+            .bind(("alarm_history", Alarm::HISTORY_TABLE_NAME))
+            .bind(("old_tags", Tag::table_name(name)))
+            .bind(("new_tags", Tag::table_name(new_name)))
+            .bind(("name", new_name))
+            .bind(("value", name))
+            .await?
+            .check()?;
+        trace!("rename response {:?}", re);
+        Ok(())
+    }
+
     pub async fn delete(db: &DBLayer, name: &str) -> XResult<()> {
         let re = db
             .db
@@ -51,3 +119,96 @@ impl Device {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::module::alarm::{AlarmDef, AlarmEvent, AlarmState, Condition};
+    use crate::module::historian::HistoryRecord;
+
+    use super::super::alarm::Alarm;
+    use super::super::history::History;
+    use super::super::DBLayer;
+    use super::Device;
+
+    #[tokio::test]
+    async fn rename_moves_history() {
+        let db = DBLayer::memory().await.unwrap();
+        Device::add(
+            &db,
+            &Device {
+                id: None,
+                name: "old".to_string(),
+                driver: "Modbus TCP".to_string(),
+                setting: None,
+                enabled: true,
+            },
+        )
+        .await
+        .unwrap();
+
+        let record = HistoryRecord {
+            device: "old".to_string(),
+            table: "table".to_string(),
+            tag: "tag".to_string(),
+            value: 1.0,
+            timestamp: 1000,
+            expires: i64::MAX,
+        };
+        let later = HistoryRecord {
+            timestamp: 2000,
+            ..record.clone()
+        };
+        History::add(&db, &[record, later]).await.unwrap();
+        Alarm::record(
+            &db,
+            &AlarmEvent {
+                name: "alarm".to_string(),
+                device: "old".to_string(),
+                table: "table".to_string(),
+                tag: "tag".to_string(),
+                state: AlarmState::ActiveUnacknowledged,
+                value: Some(1.0),
+                message: None,
+                time: Utc::now(),
+                timestamp: 1000,
+            },
+        )
+        .await
+        .unwrap();
+        Alarm::add(
+            &db,
+            &AlarmDef {
+                name: "alarm".to_string(),
+                device: "old".to_string(),
+                table: "table".to_string(),
+                tag: "tag".to_string(),
+                condition: Condition::High(0.0),
+                deadband: 0.0,
+                on_delay: 0,
+                off_delay: 0,
+                message: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        Device::rename(&db, "old", "new").await.unwrap();
+
+        let tags = ["tag".to_string()];
+        let samples = History::select(&db, "new", "table", &tags, 0, 3000)
+            .await
+            .unwrap();
+        assert_eq!(samples.len(), 2);
+        assert!(History::select(&db, "old", "table", &tags, 0, 3000)
+            .await
+            .unwrap()
+            .is_empty());
+        let events = Alarm::history(&db, Some("alarm"), 0, 3000, 10)
+            .await
+            .unwrap();
+        assert_eq!(events[0].device, "new");
+        assert_eq!(Alarm::select(&db).await.unwrap()[0].device, "new");
+    }
+}
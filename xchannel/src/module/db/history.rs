@@ -0,0 +1,71 @@
+use chrono::Utc;
+use serde::Deserialize;
+use tracing::trace;
+
+use crate::error::*;
+
+use super::super::historian::HistoryRecord;
+use super::DBLayer;
+use super::Record;
+
+#[derive(Debug, Deserialize)]
+pub struct Sample {
+    pub tag: String,
+    pub timestamp: i64,
+    pub value: f64,
+}
+
+pub struct History;
+
+impl History {
+    pub(super) const TABLE_NAME: &'static str = "history";
+
+    // Stores the records in one statement.
+    pub async fn add(db: &DBLayer, records: &[HistoryRecord]) -> XResult<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let re: Vec<Record> = db.db.insert(Self::TABLE_NAME).content(records).await?;
+        trace!("store history {:?}", re);
+        Ok(())
+    }
+
+    // Drops the records past their retention.
+    pub async fn purge(db: &DBLayer) -> XResult<()> {
+        let re = db
+            .db
+            .query("DELETE type::table($table) WHERE expires < $now")
+            .bind(("table", Self::TABLE_NAME))
+            .bind(("now", Utc::now().timestamp_millis()))
+            .await?
+            .check()?;
+        trace!("delete response {:?}", re);
+        Ok(())
+    }
+
+    // Samples of the tags between the timestamps, in milliseconds, oldest first.
+    pub async fn select(
+        db: &DBLayer,
+        device: &str,
+        table: &str,
+        tags: &[String],
+        from: i64,
+        to: i64,
+    ) -> XResult<Vec<Sample>> {
+        let mut re = db
+            .db
+            .query("SELECT tag, timestamp, value FROM type::table($table) WHERE device = $device AND table = $table_name AND tag IN $tags AND timestamp >= $from AND timestamp <= $to AND expires >= $now ORDER BY timestamp")
+            .bind(("table", Self::TABLE_NAME))
+            .bind(("device", device))
+            .bind(("table_name", table))
+            .bind(("tags", tags))
+            .bind(("from", from))
+            .bind(("to", to))
+            .bind(("now", Utc::now().timestamp_millis()))
+            .await?
+            .check()?;
+        trace!("load {:?}", re);
+        Ok(re.take(0)?)
+    }
+}
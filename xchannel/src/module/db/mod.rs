@@ -5,7 +5,9 @@ use surrealdb::engine::local::{Db, RocksDb};
 use surrealdb::sql::Thing;
 use surrealdb::Surreal;
 
+pub mod alarm;
 pub mod device;
+pub mod history;
 pub mod table;
 pub mod tag;
 
@@ -39,6 +41,17 @@ impl DBLayer {
         Ok(Self { db })
     }
 
+    // Empty database in memory with the schema applied, for tests.
+    #[cfg(test)]
+    pub async fn memory() -> XResult<Self> {
+        let db = Surreal::new::<surrealdb::engine::local::Mem>(()).await?;
+
+        db.use_ns("xchannel").use_db("xchannel").await?;
+        db.query(include_str!("../../../sql/init.surql")).await?;
+
+        Ok(Self { db })
+    }
+
     fn read_sql(path: &str) -> XResult<String> {
         let content = fs::read_to_string(path)?;
         Ok(content)
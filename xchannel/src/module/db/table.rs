@@ -4,6 +4,8 @@ use tracing::trace;
 use crate::error::*;
 
 use super::super::driver::Parameter;
+use super::alarm::Alarm;
+use super::history::History;
 use super::tag::Tag;
 use super::DBLayer;
 use super::Record;
@@ -14,10 +16,12 @@ pub struct Table {
     pub device: String,
     pub description: Option<String>,
     pub parameter: Parameter,
+    #[serde(default)]
+    pub retention: Option<u64>,
 }
 
 impl Table {
-    const TABLE_NAME: &'static str = "table";
+    pub(super) const TABLE_NAME: &'static str = "table";
 
     pub async fn select(db: &DBLayer, device: &str) -> XResult<Vec<Table>> {
         let mut re = db
@@ -37,6 +41,7 @@ impl Table {
         device: &str,
         description: Option<String>,
         parameter: &Parameter,
+        retention: Option<u64>,
     ) -> XResult<String> {
         let re: Vec<Record> = db
             .db
@@ -46,6 +51,7 @@ impl Table {
                 device: device.to_string(),
                 description,
                 parameter: parameter.clone(),
+                retention,
             })
             .await?;
         trace!("store device {:?}", re);
@@ -56,6 +62,38 @@ impl Table {
         }
     }
 
+    // Replaces the record of the table `name`; the tags and the recorded
+    // history follow a rename.
+    pub async fn update(db: &DBLayer, name: &str, table: &Table) -> XResult<()> {
+        let history = if table.name != name {
+            "UPDATE type::table($history) SET table = $name WHERE device = $device AND table = $value;
+            UPDATE type::table($alarm_history) SET table = $name WHERE device = $device AND table = $value;"
+        } else {
+            ""
+        };
+        let re = db
+            .db
+            .query(format!(
+                "BEGIN TRANSACTION;
+                UPDATE type::table($table) CONTENT $content WHERE device = $device AND name = $value;
+                UPDATE type::table($tags) SET table = $name WHERE table = $value;
+                {history}
+                COMMIT TRANSACTION;"
+            ))
+            .bind(("table", Self::TABLE_NAME))
+            .bind(("tags", Tag::table_name(&table.device)))
+            .bind(("history", History::TABLE_NAME))
+            .bind(("alarm_history", Alarm::HISTORY_TABLE_NAME))
+            .bind(("content", table))
+            .bind(("device", &table.device))
+            .bind(("name", &table.name))
+            .bind(("value", name))
+            .await?
+            .check()?;
+        trace!("update response {:?}", re);
+        Ok(())
+    }
+
     pub async fn delete(db: &DBLayer, device: &str, name: &str) -> XResult<()> {
         let re = db
             .db
@@ -83,3 +121,78 @@ impl Table {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::module::driver::Parameter;
+    use crate::module::historian::HistoryRecord;
+    use crate::module::tag::{Filter, Tag as MTag};
+    use crate::module::value::{DataType, SimpleValue};
+
+    use super::super::history::History;
+    use super::super::tag::Tag;
+    use super::super::DBLayer;
+    use super::Table;
+
+    #[tokio::test]
+    async fn rename_moves_history() {
+        let db = DBLayer::memory().await.unwrap();
+        let parameter = Parameter {
+            option: "interval".to_string(),
+            value: SimpleValue::INT(1000),
+        };
+        Table::add(&db, "old", "device", None, &parameter, None)
+            .await
+            .unwrap();
+        let mut tag = MTag {
+            name: "old".to_string(),
+            value: DataType::WORD.default_value(),
+            dtype: DataType::WORD,
+            address: Some("1.41".to_string()),
+            description: None,
+            filter: Filter::default(),
+            scaling: None,
+            unit: None,
+            expression: None,
+            retention: None,
+        };
+        Tag::add(&db, "device", "old", vec![&tag]).await.unwrap();
+        History::add(
+            &db,
+            &[HistoryRecord {
+                device: "device".to_string(),
+                table: "old".to_string(),
+                tag: "old".to_string(),
+                value: 1.0,
+                timestamp: 1000,
+                expires: i64::MAX,
+            }],
+        )
+        .await
+        .unwrap();
+
+        // unchanged names leave the history alone
+        Tag::update(&db, "device", "old", "old", &tag)
+            .await
+            .unwrap();
+        tag.name = "new".to_string();
+        Tag::update(&db, "device", "old", "old", &tag)
+            .await
+            .unwrap();
+        let table = Table {
+            name: "new".to_string(),
+            device: "device".to_string(),
+            description: None,
+            parameter,
+            retention: None,
+        };
+        Table::update(&db, "old", &table).await.unwrap();
+
+        let samples = History::select(&db, "device", "new", &["new".to_string()], 0, 2000)
+            .await
+            .unwrap();
+        assert_eq!(samples.len(), 1);
+        let tags = Tag::select(&db, "device", "new").await.unwrap();
+        assert_eq!(tags[0].name, "new");
+    }
+}
@@ -3,8 +3,10 @@ use tracing::trace;
 
 use crate::error::*;
 
-use super::super::tag::Tag as MTag;
+use super::super::tag::{Filter, Scaling, Tag as MTag};
 use super::super::value::{DataType, Value};
+use super::alarm::Alarm;
+use super::history::History;
 use super::DBLayer;
 use super::Record;
 
@@ -16,6 +18,16 @@ pub struct Tag {
     pub dtype: DataType,
     pub address: Option<String>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub scaling: Option<Scaling>,
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub expression: Option<String>,
+    #[serde(default)]
+    pub retention: Option<u64>,
 }
 
 impl From<(&str, &MTag)> for Tag {
@@ -27,6 +39,11 @@ impl From<(&str, &MTag)> for Tag {
             dtype: tag.dtype.clone(),
             address: tag.address.clone(),
             description: tag.description.clone(),
+            filter: tag.filter,
+            scaling: tag.scaling,
+            unit: tag.unit.clone(),
+            expression: tag.expression.clone(),
+            retention: tag.retention,
         }
     }
 }
@@ -34,7 +51,7 @@ impl From<(&str, &MTag)> for Tag {
 impl Tag {
     const TABLE_PREFIX: &'static str = "tag_";
 
-    fn table_name(device: &str) -> String {
+    pub(super) fn table_name(device: &str) -> String {
         format!("{}{}", Self::TABLE_PREFIX, device)
     }
 
@@ -49,6 +66,43 @@ impl Tag {
         Ok(())
     }
 
+    // Replaces the record of the tag `name`; the recorded history follows a
+    // rename.
+    pub async fn update(
+        db: &DBLayer,
+        device: &str,
+        table: &str,
+        name: &str,
+        tag: &MTag,
+    ) -> XResult<()> {
+        let history = if tag.name != name {
+            "UPDATE type::table($history) SET tag = $name WHERE device = $device AND table = $table_name AND tag = $value;
+            UPDATE type::table($alarm_history) SET tag = $name WHERE device = $device AND table = $table_name AND tag = $value;"
+        } else {
+            ""
+        };
+        let re = db
+            .db
+            .query(format!(
+                "BEGIN TRANSACTION;
+                UPDATE type::table($table) CONTENT $content WHERE table = $table_name AND name = $value;
+                {history}
+                COMMIT TRANSACTION;"
+            ))
+            .bind(("table", Self::table_name(device)))
+            .bind(("history", History::TABLE_NAME))
+            .bind(("alarm_history", Alarm::HISTORY_TABLE_NAME))
+            .bind(("content", Tag::from((table, tag))))
+            .bind(("device", device))
+            .bind(("table_name", table))
+            .bind(("name", &tag.name))
+            .bind(("value", name))
+            .await?
+            .check()?;
+        trace!("update response {:?}", re);
+        Ok(())
+    }
+
     pub async fn delete(db: &DBLayer, device: &str, table: &str, tags: &[String]) -> XResult<()> {
         for tag in tags {
             let re = db
@@ -1,22 +1,37 @@
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::collections::HashMap;
 
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 use crate::error::*;
 
-use super::driver::{Driver, Parameter, Setting, Tag as DTag};
+use super::driver::{Driver, DriverStats, LinkState, Parameter, Setting, Tag as DTag};
+use super::event::{Events, TagEvent};
 use super::table::{Table, TableInfo};
-use super::tag::Tag;
+use super::tag::{QualityReason, Tag, TagInfo, TagValue};
 
 pub struct Device {
     name: String,
     driver_name: String,
     setting: Option<Setting>,
 
-    driver: Box<dyn Driver + Send>,
+    driver: Arc<dyn Driver + Send + Sync>,
 
-    tables: Mutex<HashMap<String, Table>>,
+    tables: Mutex<HashMap<String, Arc<Table>>>,
+    events: Events,
+    enabled: AtomicBool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub enabled: bool,
+    pub link: LinkState,
+    // latest of the tables' last fully good reads
+    pub last_poll: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub stats: DriverStats,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -25,13 +40,17 @@ pub struct DeviceInfo {
     pub name: String,
     pub driver: String,
     pub setting: Option<Setting>,
+    #[serde(flatten)]
+    pub status: DeviceStatus,
 }
 
 impl Device {
+    // The device starts disabled, with the driver stopped.
     pub fn new(
         name: &str,
-        driver: Box<dyn Driver + Send>,
+        driver: Arc<dyn Driver + Send + Sync>,
         setting: &Option<Setting>,
+        events: Events,
     ) -> XResult<Self> {
         if let Some(setting) = &setting {
             driver.setting(setting)?;
@@ -43,6 +62,8 @@ impl Device {
             driver,
             setting: setting.clone(),
             tables: Mutex::new(HashMap::new()),
+            events,
+            enabled: AtomicBool::new(false),
         })
     }
 
@@ -52,9 +73,107 @@ impl Device {
             name: self.name.to_string(),
             driver: self.driver_name.to_string(),
             setting: self.setting.clone(),
+            status: self.status(),
+        }
+    }
+
+    pub fn status(&self) -> DeviceStatus {
+        let tables = self.tables.lock().unwrap();
+
+        DeviceStatus {
+            enabled: self.is_enabled(),
+            link: self.driver.link_state(),
+            last_poll: tables.values().filter_map(|table| table.last_poll()).max(),
+            stats: self.driver.stats(),
         }
     }
 
+    pub fn setting(&self) -> Option<Setting> {
+        self.setting.clone()
+    }
+
+    // Applies a new setting through the driver, restarting it when enabled;
+    // the tables keep polling the same driver. When the driver does not start
+    // with it, the old setting is back, and the device is disabled unless the
+    // driver starts with that one.
+    pub async fn set_setting(&mut self, setting: Setting) -> XResult<()> {
+        self.driver.setting(&setting)?;
+        let old = self.setting.replace(setting);
+
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        self.driver.stop().await;
+        let Err(err) = self.driver.start().await else {
+            return Ok(());
+        };
+
+        self.setting = old;
+        let restarted = match &self.setting {
+            Some(old) => self.driver.setting(old).is_ok() && self.driver.start().await.is_ok(),
+            None => false,
+        };
+        if !restarted {
+            self.set_enabled(false).await?;
+        }
+        Err(err)
+    }
+
+    // Tag changes are published under the new name from now on.
+    pub fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+
+        if self.is_enabled() {
+            let tables = self.tables.lock().unwrap();
+            for table in tables.values() {
+                table.start(self.driver.clone(), self.name.clone(), self.events.clone());
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    // A disabled device keeps its tables and tags but stops the driver and the
+    // pollers; its polled tags turn bad.
+    pub async fn set_enabled(&self, enabled: bool) -> XResult<()> {
+        if self.is_enabled() == enabled {
+            return Ok(());
+        }
+
+        if enabled {
+            self.driver.start().await?;
+            self.enabled.store(true, Ordering::Relaxed);
+            let tables = self.tables.lock().unwrap();
+            for table in tables.values() {
+                table.start(self.driver.clone(), self.name.clone(), self.events.clone());
+            }
+        } else {
+            self.enabled.store(false, Ordering::Relaxed);
+            self.driver.stop().await;
+            let tables = self.tables.lock().unwrap();
+            for table in tables.values() {
+                table.stop();
+                for (name, value) in table.set_bad(QualityReason::Disabled) {
+                    self.events.publish(TagEvent {
+                        device: self.name.clone(),
+                        table: table.name(),
+                        name,
+                        value,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Stops the driver for good, before the device is dropped.
+    pub async fn close(&self) {
+        self.driver.stop().await;
+    }
+
     pub fn driver_name(&self) -> String {
         self.driver_name.to_string()
     }
@@ -64,6 +183,7 @@ impl Device {
         name: &str,
         description: Option<String>,
         parameter: &Parameter,
+        retention: Option<u64>,
     ) -> XResult<()> {
         let mut tables = self.tables.lock().unwrap();
 
@@ -76,12 +196,48 @@ impl Device {
 
         self.driver.table_parameter(parameter)?;
 
-        let table = Table::new(name.to_string(), description, parameter.clone());
+        let table = Arc::new(Table::new(
+            name.to_string(),
+            description,
+            parameter.clone(),
+            retention,
+        ));
+        if self.is_enabled() {
+            table.start(self.driver.clone(), self.name.clone(), self.events.clone());
+        }
         tables.insert(name.to_string(), table);
 
         Ok(())
     }
 
+    // Changes the settings of a table, or renames it, keeping its tags.
+    pub fn update_table(&self, name: &str, info: TableInfo) -> XResult<()> {
+        let mut tables = self.tables.lock().unwrap();
+
+        if info.name != name && tables.contains_key(&info.name) {
+            return Err(XError::new(
+                XErrorKind::TableError,
+                &format!("{} already exists", info.name),
+            ));
+        }
+        if !tables.contains_key(name) {
+            return Err(XError::new(
+                XErrorKind::TableError,
+                &format!("{name} not found"),
+            ));
+        }
+        self.driver.table_parameter(&info.parameter)?;
+
+        let old = tables.remove(name).unwrap();
+        let table = Arc::new(old.replace(info));
+        if self.is_enabled() {
+            table.start(self.driver.clone(), self.name.clone(), self.events.clone());
+        }
+        tables.insert(table.name(), table);
+
+        Ok(())
+    }
+
     pub fn del_table<'a, 'b>(&'b self, name: &'a str) -> XResult<Option<&'a str>> {
         let mut tables = self.tables.lock().unwrap();
 
@@ -106,7 +262,7 @@ impl Device {
             .collect()
     }
 
-    pub fn get_tags(&self, table: &str, name: Option<String>) -> XResult<Vec<Tag>> {
+    pub fn get_tags(&self, table: &str, name: Option<String>) -> XResult<Vec<TagInfo>> {
         let tables = self.tables.lock().unwrap();
 
         if let Some(table) = tables.get(table) {
@@ -119,6 +275,58 @@ impl Device {
         }
     }
 
+    pub fn get_values(&self, table: &str, names: &[String]) -> XResult<Vec<TagInfo>> {
+        let tables = self.tables.lock().unwrap();
+
+        if let Some(table) = tables.get(table) {
+            table.get_values(names)
+        } else {
+            Err(XError::new(
+                XErrorKind::TableError,
+                &format!("{table} not found"),
+            ))
+        }
+    }
+
+    pub fn retention(&self, table: &str, name: &str) -> Option<u64> {
+        let tables = self.tables.lock().unwrap();
+
+        tables.get(table).and_then(|table| table.retention(name))
+    }
+
+    pub fn get_tag(&self, table: &str, name: &str) -> XResult<Tag> {
+        let tables = self.tables.lock().unwrap();
+
+        if let Some(table) = tables.get(table) {
+            table.get_tag(name)
+        } else {
+            Err(XError::new(
+                XErrorKind::TableError,
+                &format!("{table} not found"),
+            ))
+        }
+    }
+
+    // Stores values produced outside the driver; returns the changes to
+    // publish.
+    pub fn update_values(
+        &self,
+        table: &str,
+        names: &[String],
+        values: Vec<TagValue>,
+    ) -> XResult<Vec<(String, TagValue)>> {
+        let tables = self.tables.lock().unwrap();
+
+        if let Some(table) = tables.get(table) {
+            Ok(table.update_values(names, values))
+        } else {
+            Err(XError::new(
+                XErrorKind::TableError,
+                &format!("{table} not found"),
+            ))
+        }
+    }
+
     pub fn add_tags(&self, table: &str, tags: &[Tag]) -> XResult<()> {
         let tables = self.tables.lock().unwrap();
 
@@ -129,7 +337,27 @@ impl Device {
                 .map(|tag| tag.into())
                 .collect();
             self.driver.tag(&dtags)?;
-            table.add_tags(tags)
+            table.add_tags(tags)?;
+            if !self.is_enabled() {
+                table.set_bad(QualityReason::Disabled);
+            }
+            Ok(())
+        } else {
+            Err(XError::new(
+                XErrorKind::TableError,
+                &format!("{table} not found"),
+            ))
+        }
+    }
+
+    pub fn update_tag(&self, table: &str, name: &str, tag: Tag) -> XResult<()> {
+        let tables = self.tables.lock().unwrap();
+
+        if let Some(table) = tables.get(table) {
+            if tag.address.is_some() {
+                self.driver.tag(&[(&tag).into()])?;
+            }
+            table.update_tag(name, tag)
         } else {
             Err(XError::new(
                 XErrorKind::TableError,
@@ -150,4 +378,185 @@ impl Device {
             ))
         }
     }
+
+    // Converts the JSON value to the raw tag value and writes it through the
+    // driver.
+    pub async fn write_tag(
+        &self,
+        table: &str,
+        name: &str,
+        value: &serde_json::Value,
+    ) -> XResult<()> {
+        if !self.is_enabled() {
+            return Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{} is disabled", self.name),
+            ));
+        }
+
+        let tag = {
+            let tables = self.tables.lock().unwrap();
+            let table = tables.get(table).ok_or_else(|| {
+                XError::new(XErrorKind::TableError, &format!("{table} not found"))
+            })?;
+            table.get_tag(name)?
+        };
+
+        if tag.address.is_none() {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                &format!("{name} has no address"),
+            ));
+        }
+
+        let value = tag.raw_value(value)?;
+        self.driver.write(&(&tag).into(), &value).await
+    }
+}
+
+#[cfg(all(test, feature = "modbus"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+
+    use crate::drivers::modbus::modbus_tcp::ModbusTcp;
+    use crate::error::*;
+    use crate::module::driver::{
+        Capabilities, Driver, DriverInfo, Parameter, Setting, Tag as DTag, Validate,
+    };
+    use crate::module::event::Events;
+    use crate::module::tag::{Filter, Tag};
+    use crate::module::value::{DataType, SimpleValue};
+
+    use super::Device;
+
+    // Starts unless its setting has a "fail" parameter.
+    #[derive(Default)]
+    struct Flaky {
+        setting: Mutex<Setting>,
+    }
+
+    impl Validate for Flaky {
+        fn table_parameter(&self, _parameter: &Parameter) -> XResult<()> {
+            Ok(())
+        }
+
+        fn tag(&self, _tags: &[DTag]) -> XResult<()> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Driver for Flaky {
+        fn info(&self) -> DriverInfo {
+            DriverInfo {
+                name: "flaky".to_string(),
+                description: String::new(),
+                version: String::new(),
+                capabilities: Capabilities::default(),
+            }
+        }
+
+        fn setting(&self, setting: &Setting) -> XResult<()> {
+            *self.setting.lock().unwrap() = setting.clone();
+            Ok(())
+        }
+
+        async fn start(&self) -> XResult<()> {
+            if self
+                .setting
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|p| p.option == "fail")
+            {
+                Err(XError::new(XErrorKind::DriverError, "start failed"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn setting(option: &str) -> Setting {
+        vec![Parameter {
+            option: option.to_string(),
+            value: SimpleValue::INT(1),
+        }]
+    }
+
+    fn tag(name: &str, dtype: DataType, address: &str) -> Tag {
+        Tag {
+            name: name.to_string(),
+            value: dtype.default_value(),
+            dtype,
+            address: Some(address.to_string()),
+            description: None,
+            filter: Filter::default(),
+            scaling: None,
+            unit: None,
+            expression: None,
+            retention: None,
+        }
+    }
+
+    #[test]
+    fn bad_address_rejected() {
+        let device = Device::new(
+            "device",
+            Arc::new(ModbusTcp::default()),
+            &None,
+            Events::default(),
+        )
+        .unwrap();
+        let interval = Parameter {
+            option: "interval".to_string(),
+            value: SimpleValue::INT(1000),
+        };
+        device.add_table("table", None, &interval, None).unwrap();
+
+        assert!(device
+            .add_tags("table", &[tag("coil", DataType::WORD, "1.01")])
+            .is_err());
+        assert!(device.get_tags("table", None).unwrap().is_empty());
+
+        device
+            .add_tags("table", &[tag("word", DataType::WORD, "1.41")])
+            .unwrap();
+        assert!(device
+            .update_tag("table", "word", tag("word", DataType::WORD, "1.4x"))
+            .is_err());
+        let tags = device.get_tags("table", None).unwrap();
+        assert_eq!(tags[0].address.as_deref(), Some("1.41"));
+    }
+
+    #[tokio::test]
+    async fn failed_setting_restored() {
+        let mut device = Device::new(
+            "device",
+            Arc::new(Flaky::default()),
+            &Some(setting("ok")),
+            Events::default(),
+        )
+        .unwrap();
+        device.set_enabled(true).await.unwrap();
+
+        // back to the old setting, which still starts
+        assert!(device.set_setting(setting("fail")).await.is_err());
+        assert_eq!(device.setting().unwrap()[0].option, "ok");
+        assert!(device.is_enabled());
+
+        // nothing to go back to
+        let mut device = Device::new(
+            "device",
+            Arc::new(Flaky::default()),
+            &None,
+            Events::default(),
+        )
+        .unwrap();
+        device.set_enabled(true).await.unwrap();
+        assert!(device.set_setting(setting("fail")).await.is_err());
+        assert!(device.setting().is_none());
+        assert!(!device.is_enabled());
+    }
 }
@@ -1,27 +1,49 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
 use log::warn;
-use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, Mutex, Notify};
+use tokio::time::{self, MissedTickBehavior};
 
-use crate::drivers::modbus::modbus_tcp::ModbusTcp;
+use crate::drivers;
 use crate::error::*;
 
+use super::alarm::{Alarm, AlarmDef, AlarmEvent, AlarmInfo, AlarmState};
+use super::computed::{Computed, Formula, TagRef};
 use super::db;
+use super::db::alarm::Alarm as DBAlarm;
 use super::db::device::Device as DBDevice;
+use super::db::history::History as DBHistory;
 use super::db::table::Table as DBTable;
 use super::db::tag::Tag as DBTag;
 use super::device::Device;
-use super::device::DeviceInfo;
+use super::device::{DeviceInfo, DeviceStatus};
 use super::driver::DriverInfo;
-use super::driver::{Driver, Parameter, Setting};
+use super::driver::{Parameter, Setting};
+use super::event::{Events, Pattern, TagEvent};
+use super::historian::{self, Aggregate, HistoryRecord, Point, TagHistory};
+use super::registry::Registry;
 use super::table::TableInfo;
-use super::tag::Tag;
+use super::tag::{Tag, TagInfo};
+
+type Devices = HashMap<String, (String, Device)>;
+
+// Recorded changes are written in batches, expired ones dropped now and then.
+const HISTORY_FLUSH: Duration = Duration::from_secs(1);
+const HISTORY_PURGE: Duration = Duration::from_secs(60);
 
 pub struct DeviceMgr {
-    devices: Mutex<HashMap<String, (String, Device)>>,
+    devices: Mutex<Devices>,
     ids: Mutex<HashMap<String, String>>,
-    drivers: HashMap<String, DriverInfo>,
+    drivers: Registry,
+    events: Events,
+    computed: std::sync::Mutex<Computed>,
+    alarms: std::sync::Mutex<HashMap<String, Alarm>>,
+    // wakes the alarm watcher when an alarm starts a delay on its own
+    alarm_delay: Arc<Notify>,
     db: db::DBLayer,
 }
 
@@ -32,23 +54,65 @@ impl DeviceMgr {
         let mut mgr = DeviceMgr {
             devices: Mutex::new(HashMap::new()),
             ids: Mutex::new(HashMap::new()),
-            drivers: HashMap::new(),
+            drivers: Registry::default(),
+            events: Events::default(),
+            computed: std::sync::Mutex::new(Computed::default()),
+            alarms: std::sync::Mutex::new(HashMap::new()),
+            alarm_delay: Arc::new(Notify::new()),
             db,
         };
 
-        mgr.drivers.insert(
-            ModbusTcp::default().info().name,
-            ModbusTcp::default().info(),
-        );
+        drivers::register(&mut mgr.drivers);
 
         let mgr = Arc::new(mgr);
         mgr.load().await?;
+        tokio::spawn(Self::calculate(Arc::downgrade(&mgr), mgr.subscribe()));
+        tokio::spawn(Self::watch_alarms(
+            Arc::downgrade(&mgr),
+            mgr.subscribe(),
+            mgr.alarm_delay.clone(),
+        ));
+        tokio::spawn(Self::record_history(Arc::downgrade(&mgr), mgr.subscribe()));
 
         Ok(mgr)
     }
 
+    pub fn subscribe(&self) -> broadcast::Receiver<TagEvent> {
+        self.events.subscribe()
+    }
+
+    // Current values of the tags matching any of the patterns, as events.
+    pub async fn snapshot(&self, patterns: &[Pattern]) -> Vec<TagEvent> {
+        let devices = self.devices.lock().await;
+        let mut events = Vec::new();
+
+        for (device, (_, dev)) in devices.iter() {
+            for table in dev.get_tables(None) {
+                let Ok(tags) = dev.get_values(&table.name, &[]) else {
+                    continue;
+                };
+                events.extend(
+                    tags.into_iter()
+                        .filter(|tag| {
+                            patterns
+                                .iter()
+                                .any(|p| p.matches(device, &table.name, &tag.name))
+                        })
+                        .map(|tag| TagEvent {
+                            device: device.clone(),
+                            table: table.name.clone(),
+                            name: tag.name,
+                            value: tag.value,
+                        }),
+                );
+            }
+        }
+
+        events
+    }
+
     pub fn get_drivers(&self) -> Vec<DriverInfo> {
-        self.drivers.values().cloned().collect()
+        self.drivers.infos()
     }
 
     // name, id, driver
@@ -81,7 +145,7 @@ impl DeviceMgr {
         driver: &str,
         setting: &Option<Setting>,
     ) -> XResult<String> {
-        if !self.drivers.contains_key(driver) {
+        if !self.drivers.contains(driver) {
             return Err(XError::new(
                 XErrorKind::DriverError,
                 &format!("{driver} not found"),
@@ -106,10 +170,17 @@ impl DeviceMgr {
                 name: name.to_string(),
                 driver: driver.to_string(),
                 setting: setting.clone(),
+                enabled: true,
             },
         )
         .await?;
 
+        // started only once stored; a device that fails to start is not kept
+        if let Err(err) = device.set_enabled(true).await {
+            DBDevice::delete(&self.db, name).await?;
+            return Err(err);
+        }
+
         devices.insert(name.to_string(), (id.clone(), device));
         let mut ids = self.ids.lock().await;
         ids.insert(id.clone(), name.to_string());
@@ -124,9 +195,109 @@ impl DeviceMgr {
             warn!("delete {name} from db, {}", err.to_string());
         }
 
+        let removed = devices.remove(name);
+        if let Some((_, dev)) = &removed {
+            dev.close().await;
+        }
+        self.drop_computed(&devices, |target| target.device == name);
+
+        removed.map_or_else(|| Ok(None), |_| Ok(Some(name)))
+    }
+
+    pub async fn device_status(&self, name: &str) -> XResult<DeviceStatus> {
+        let devices = self.devices.lock().await;
+
         devices
-            .remove(name)
-            .map_or_else(|| Ok(None), |_| Ok(Some(name)))
+            .get(name)
+            .map(|(_, dev)| dev.status())
+            .ok_or_else(|| XError::new(XErrorKind::DeviceError, &format!("{name} not found")))
+    }
+
+    pub async fn set_setting(&self, name: &str, setting: &Setting) -> XResult<()> {
+        let mut devices = self.devices.lock().await;
+
+        let Some((_, dev)) = devices.get_mut(name) else {
+            return Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{name} not found"),
+            ));
+        };
+
+        // checked on a driver of its own, the running one keeps its setting
+        self.drivers.create(&dev.driver_name())?.setting(setting)?;
+        let old = dev.setting();
+        DBDevice::set_setting(&self.db, name, Some(setting)).await?;
+
+        if let Err(err) = dev.set_setting(setting.clone()).await {
+            if let Err(err) = DBDevice::set_setting(&self.db, name, old.as_ref()).await {
+                warn!("restore setting of {name}, {err}");
+            }
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    // Renames a device while no computed tag of another device refers to it;
+    // its own computed tags and the alarms watching it move along.
+    pub async fn rename_device(&self, name: &str, new_name: &str) -> XResult<()> {
+        let mut devices = self.devices.lock().await;
+
+        if devices.contains_key(new_name) {
+            return Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{new_name} already exists"),
+            ));
+        }
+        if !devices.contains_key(name) {
+            return Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{name} not found"),
+            ));
+        }
+        let in_device = |r: &TagRef| r.device == name;
+        self.check_unread(in_device, in_device)?;
+
+        DBDevice::rename(&self.db, name, new_name).await?;
+
+        let (id, mut dev) = devices.remove(name).unwrap();
+        dev.rename(new_name);
+        devices.insert(new_name.to_string(), (id.clone(), dev));
+        self.ids.lock().await.insert(id, new_name.to_string());
+        for alarm in self.alarms.lock().unwrap().values_mut() {
+            if alarm.def().device == name {
+                alarm.set_device(new_name);
+            }
+        }
+
+        let targets = {
+            let mut computed = self.computed.lock().unwrap();
+            let moved: Vec<TagRef> = computed
+                .targets()
+                .into_iter()
+                .filter(|target| in_device(target))
+                .map(|target| TagRef::new(new_name, &target.table, &target.tag))
+                .collect();
+            computed.retain(|target| !in_device(target));
+            Self::register_formulas(&devices, &mut computed, &moved)
+        };
+        self.evaluate(&devices, &targets);
+
+        Ok(())
+    }
+
+    pub async fn set_enabled(&self, name: &str, enabled: bool) -> XResult<()> {
+        let devices = self.devices.lock().await;
+
+        let Some((_, dev)) = devices.get(name) else {
+            return Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{name} not found"),
+            ));
+        };
+
+        dev.set_enabled(enabled).await?;
+        DBDevice::set_enabled(&self.db, name, enabled).await
     }
 
     pub async fn get_tables(&self, device: &str, name: Option<String>) -> XResult<Vec<TableInfo>> {
@@ -150,6 +321,7 @@ impl DeviceMgr {
         name: &str,
         description: Option<String>,
         param: &Parameter,
+        retention: Option<u64>,
     ) -> XResult<()> {
         let devices = self.devices.lock().await;
 
@@ -162,9 +334,114 @@ impl DeviceMgr {
 
         let (_, dev) = devices.get(device).unwrap();
 
-        dev.add_table(name, description.clone(), param)?;
+        dev.add_table(name, description.clone(), param, retention)?;
 
-        DBTable::add(&self.db, name, device, description, param).await?;
+        DBTable::add(&self.db, name, device, description, param, retention).await?;
+
+        Ok(())
+    }
+
+    // Applies `update` to the settings of a table. A table is only renamed
+    // while no alarm or computed tag outside it refers to it; its own computed
+    // tags move along.
+    pub async fn update_table(
+        &self,
+        device: &str,
+        name: &str,
+        update: impl FnOnce(TableInfo) -> TableInfo,
+    ) -> XResult<()> {
+        let devices = self.devices.lock().await;
+
+        let Some((_, dev)) = devices.get(device) else {
+            return Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{device} not found"),
+            ));
+        };
+        let old = dev
+            .get_tables(None)
+            .into_iter()
+            .find(|table| table.name == name)
+            .ok_or_else(|| XError::new(XErrorKind::TableError, &format!("{name} not found")))?;
+        let info = update(old.clone());
+
+        let in_table = |r: &TagRef| r.device == device && r.table == name;
+        let renamed = info.name != name;
+        if renamed {
+            self.check_unreferenced(in_table, in_table)?;
+        }
+        dev.update_table(name, info.clone())?;
+
+        let table = DBTable {
+            name: info.name.clone(),
+            device: device.to_string(),
+            description: info.description.clone(),
+            parameter: info.parameter.clone(),
+            retention: info.retention,
+        };
+        if let Err(err) = DBTable::update(&self.db, name, &table).await {
+            // nothing was stored, the table stays as it was
+            if let Err(err) = dev.update_table(&info.name, old) {
+                warn!("restore table {name}, {err}");
+            }
+            return Err(err);
+        }
+
+        if renamed {
+            let targets = {
+                let mut computed = self.computed.lock().unwrap();
+                let moved: Vec<TagRef> = computed
+                    .targets()
+                    .into_iter()
+                    .filter(|target| in_table(target))
+                    .map(|target| TagRef::new(device, &info.name, &target.tag))
+                    .collect();
+                computed.retain(|target| !in_table(target));
+                Self::register_formulas(&devices, &mut computed, &moved)
+            };
+            self.evaluate(&devices, &targets);
+        }
+
+        Ok(())
+    }
+
+    // Fails when an alarm or a computed tag not matching `except` refers to a
+    // tag matching `refs`.
+    fn check_unreferenced(
+        &self,
+        refs: impl Fn(&TagRef) -> bool,
+        except: impl Fn(&TagRef) -> bool,
+    ) -> XResult<()> {
+        self.check_unread(&refs, except)?;
+
+        let alarms = self.alarms.lock().unwrap();
+        if let Some(alarm) = alarms.values().find(|alarm| {
+            let def = alarm.def();
+            refs(&TagRef::new(&def.device, &def.table, &def.tag))
+        }) {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                &format!("watched by alarm {}", alarm.def().name),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Fails when a computed tag not matching `except` reads a tag matching
+    // `refs`.
+    fn check_unread(
+        &self,
+        refs: impl Fn(&TagRef) -> bool,
+        except: impl Fn(&TagRef) -> bool,
+    ) -> XResult<()> {
+        let readers = self.computed.lock().unwrap().dependents(&refs);
+        if let Some(reader) = readers.iter().find(|target| !except(target)) {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                &format!("read by {}.{}.{}", reader.device, reader.table, reader.tag),
+            ));
+        }
 
         Ok(())
     }
@@ -178,6 +455,9 @@ impl DeviceMgr {
 
         if let Some((_, dev)) = devices.get(device) {
             let re = dev.del_table(name);
+            self.drop_computed(&devices, |target| {
+                target.device == device && target.table == name
+            });
             DBTable::delete(&self.db, device, name).await?;
 
             re
@@ -194,7 +474,7 @@ impl DeviceMgr {
         device: &str,
         table: &str,
         name: Option<String>,
-    ) -> XResult<Vec<Tag>> {
+    ) -> XResult<Vec<TagInfo>> {
         let devices = self.devices.lock().await;
 
         if let Some((_, dev)) = devices.get(device) {
@@ -211,23 +491,42 @@ impl DeviceMgr {
         let devices = self.devices.lock().await;
 
         if let Some((_, dev)) = devices.get(device) {
-            let result = dev.add_tags(table, &tags);
-            let mut index = tags.len() as i32;
+            // tags up to the first bad expression are added, as with conflicts
+            let mut computed = self.computed.lock().unwrap().clone();
+            let (valid, invalid) =
+                Self::add_formulas(&devices, &mut computed, device, table, &tags);
+
+            let result = dev
+                .add_tags(table, &tags[..valid])
+                .and(invalid.map_or(Ok(()), Err));
+            let mut index = valid as i32;
 
             if let Err(err) = &result {
                 if err.kind() != XErrorKind::TagError {
                     return Err(err.clone());
                 } else {
-                    index = err.get_index() - 1;
+                    index = index.min(err.get_index() - 1);
                 }
             }
-            DBTag::add(
-                &self.db,
-                device,
-                table,
-                tags.iter().take(index as usize).collect(),
-            )
-            .await?;
+
+            let added = &tags[..index as usize];
+            computed.retain(|target| {
+                target.device != device
+                    || target.table != table
+                    || !tags[index as usize..]
+                        .iter()
+                        .any(|tag| tag.name == target.tag)
+            });
+            *self.computed.lock().unwrap() = computed;
+
+            let targets: Vec<TagRef> = added
+                .iter()
+                .filter(|tag| tag.expression.is_some())
+                .map(|tag| TagRef::new(device, table, &tag.name))
+                .collect();
+            self.evaluate(&devices, &targets);
+
+            DBTag::add(&self.db, device, table, added.iter().collect()).await?;
             result
         } else {
             Err(XError::new(
@@ -237,11 +536,230 @@ impl DeviceMgr {
         }
     }
 
+    // Registers the expressions of the tags to add. Inputs must exist already
+    // or come earlier in `tags`. Returns how many tags passed and the error of
+    // the first one that did not.
+    fn add_formulas(
+        devices: &Devices,
+        computed: &mut Computed,
+        device: &str,
+        table: &str,
+        tags: &[Tag],
+    ) -> (usize, Option<XError>) {
+        for (i, tag) in tags.iter().enumerate() {
+            let Some(expression) = &tag.expression else {
+                continue;
+            };
+
+            let target = TagRef::new(device, table, &tag.name);
+            let result = Formula::new(&target, expression).and_then(|formula| {
+                let missing = formula.inputs().iter().find(|input| {
+                    let earlier = input.device == device
+                        && input.table == table
+                        && tags[..i].iter().any(|tag| tag.name == input.tag);
+                    let existing = devices
+                        .get(&input.device)
+                        .is_some_and(|(_, dev)| dev.get_tag(&input.table, &input.tag).is_ok());
+                    !earlier && !existing
+                });
+                if let Some(input) = missing {
+                    return Err(XError::new(
+                        XErrorKind::TagError,
+                        &format!("{}.{}.{} not found", input.device, input.table, input.tag),
+                    ));
+                }
+
+                computed.insert(target, formula)
+            });
+
+            if let Err(err) = result {
+                return (i, Some(err.with_index(i as i32 + 1)));
+            }
+        }
+
+        (tags.len(), None)
+    }
+
+    // Registers the expressions of the computed tags `targets` as they are
+    // configured now; returns those registered.
+    fn register_formulas(
+        devices: &Devices,
+        computed: &mut Computed,
+        targets: &[TagRef],
+    ) -> Vec<TagRef> {
+        let mut registered = Vec::new();
+
+        for target in targets {
+            let expression = devices
+                .get(&target.device)
+                .and_then(|(_, dev)| dev.get_tag(&target.table, &target.tag).ok())
+                .and_then(|tag| tag.expression);
+            let Some(expression) = expression else {
+                continue;
+            };
+
+            let result = Formula::new(target, &expression)
+                .and_then(|formula| computed.insert(target.clone(), formula));
+            match result {
+                Ok(()) => registered.push(target.clone()),
+                Err(err) => warn!(
+                    "register {}.{}.{}, {err}",
+                    target.device, target.table, target.tag
+                ),
+            }
+        }
+
+        registered
+    }
+
+    // Forgets the computed tags matching `dropped` and re-evaluates those that
+    // read them, which turns them bad.
+    fn drop_computed(&self, devices: &Devices, dropped: impl Fn(&TagRef) -> bool) {
+        let targets = {
+            let mut computed = self.computed.lock().unwrap();
+            computed.retain(|target| !dropped(target));
+            computed.dependents(&dropped)
+        };
+
+        self.evaluate(devices, &targets);
+    }
+
+    // Recomputes the tags and publishes their changes.
+    fn evaluate(&self, devices: &Devices, targets: &[TagRef]) {
+        let computed = self.computed.lock().unwrap();
+        let lookup = |input: &TagRef| {
+            let (_, dev) = devices.get(&input.device)?;
+            let mut values = dev
+                .get_values(&input.table, std::slice::from_ref(&input.tag))
+                .ok()?;
+            values.pop().map(|info| info.value)
+        };
+
+        for target in targets {
+            let Some(formula) = computed.get(target) else {
+                continue;
+            };
+            let Some((_, dev)) = devices.get(&target.device) else {
+                continue;
+            };
+            let Ok(tag) = dev.get_tag(&target.table, &target.tag) else {
+                continue;
+            };
+
+            let value = formula.eval(tag.dtype, lookup);
+            let Ok(changes) = dev.update_values(&target.table, &[tag.name], vec![value]) else {
+                continue;
+            };
+            for (name, value) in changes {
+                self.events.publish(TagEvent {
+                    device: target.device.clone(),
+                    table: target.table.clone(),
+                    name,
+                    value,
+                });
+            }
+        }
+    }
+
+    // Re-evaluates computed tags as their inputs change. Their own changes are
+    // published again, so tags computed from computed tags follow.
+    async fn calculate(mgr: Weak<DeviceMgr>, mut events: broadcast::Receiver<TagEvent>) {
+        loop {
+            let event = events.recv().await;
+            let Some(mgr) = mgr.upgrade() else {
+                break;
+            };
+
+            let targets = match event {
+                Ok(event) => {
+                    let input = TagRef::new(&event.device, &event.table, &event.name);
+                    mgr.computed.lock().unwrap().dependents(|i| *i == input)
+                }
+                // changes were missed, catch up on everything
+                Err(RecvError::Lagged(_)) => mgr.computed.lock().unwrap().targets(),
+                Err(RecvError::Closed) => break,
+            };
+            if targets.is_empty() {
+                continue;
+            }
+
+            let devices = mgr.devices.lock().await;
+            mgr.evaluate(&devices, &targets);
+        }
+    }
+
+    // Applies `update` to a tag. A tag is only renamed while no alarm or other
+    // computed tag refers to it.
+    pub async fn update_tag(
+        &self,
+        device: &str,
+        table: &str,
+        name: &str,
+        update: impl FnOnce(Tag) -> XResult<Tag>,
+    ) -> XResult<()> {
+        let devices = self.devices.lock().await;
+
+        let Some((_, dev)) = devices.get(device) else {
+            return Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{device} not found"),
+            ));
+        };
+        let old_tag = dev.get_tag(table, name)?;
+        let tag = update(old_tag.clone())?;
+
+        let old = TagRef::new(device, table, name);
+        if tag.name != name {
+            self.check_unreferenced(|r| *r == old, |r| *r == old)?;
+        }
+
+        let mut computed = self.computed.lock().unwrap().clone();
+        computed.retain(|target| *target != old);
+        let (_, invalid) = Self::add_formulas(
+            &devices,
+            &mut computed,
+            device,
+            table,
+            std::slice::from_ref(&tag),
+        );
+        if let Some(err) = invalid {
+            // a single tag, not part of a list
+            return Err(err.with_index(-1));
+        }
+
+        dev.update_tag(table, name, tag.clone())?;
+        if let Err(err) = DBTag::update(&self.db, device, table, name, &tag).await {
+            // nothing was stored, the tag stays as it was
+            if let Err(err) = dev.update_tag(table, &tag.name, old_tag) {
+                warn!("restore tag {name}, {err}");
+            }
+            return Err(err);
+        }
+        *self.computed.lock().unwrap() = computed;
+
+        // the tag and whatever reads it
+        let target = TagRef::new(device, table, &tag.name);
+        let mut targets = self
+            .computed
+            .lock()
+            .unwrap()
+            .dependents(|input| *input == target);
+        if tag.expression.is_some() {
+            targets.push(target);
+        }
+        self.evaluate(&devices, &targets);
+
+        Ok(())
+    }
+
     pub async fn del_tags(&self, device: &str, table: &str, tags: Vec<String>) -> XResult<()> {
         let devices = self.devices.lock().await;
 
         if let Some((_, dev)) = devices.get(device) {
             let _ = dev.del_tags(table, &tags);
+            self.drop_computed(&devices, |target| {
+                target.device == device && target.table == table && tags.contains(&target.tag)
+            });
             DBTag::delete(&self.db, device, table, &tags).await
         } else {
             Err(XError::new(
@@ -251,26 +769,331 @@ impl DeviceMgr {
         }
     }
 
+    pub async fn get_values(
+        &self,
+        device: &str,
+        table: &str,
+        names: &[String],
+    ) -> XResult<Vec<TagInfo>> {
+        let devices = self.devices.lock().await;
+
+        if let Some((_, dev)) = devices.get(device) {
+            dev.get_values(table, names)
+        } else {
+            Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{device} not found"),
+            ))
+        }
+    }
+
+    // Writes the tags one by one; returns one result per tag, in order.
+    pub async fn write_tags(
+        &self,
+        device: &str,
+        table: &str,
+        values: &[(String, serde_json::Value)],
+    ) -> XResult<Vec<XResult<()>>> {
+        let devices = self.devices.lock().await;
+
+        if let Some((_, dev)) = devices.get(device) {
+            let mut results = Vec::with_capacity(values.len());
+            for (name, value) in values {
+                results.push(dev.write_tag(table, name, value).await);
+            }
+            Ok(results)
+        } else {
+            Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{device} not found"),
+            ))
+        }
+    }
+
+    pub async fn write_tag(
+        &self,
+        device: &str,
+        table: &str,
+        name: &str,
+        value: &serde_json::Value,
+    ) -> XResult<()> {
+        let devices = self.devices.lock().await;
+
+        if let Some((_, dev)) = devices.get(device) {
+            dev.write_tag(table, name, value).await
+        } else {
+            Err(XError::new(
+                XErrorKind::DeviceError,
+                &format!("{device} not found"),
+            ))
+        }
+    }
+
+    pub fn get_alarms(&self, active: bool) -> Vec<AlarmInfo> {
+        let alarms = self.alarms.lock().unwrap();
+
+        alarms
+            .values()
+            .filter(|alarm| !active || alarm.state() != AlarmState::Normal)
+            .map(|alarm| alarm.info())
+            .collect()
+    }
+
+    pub async fn add_alarm(&self, def: AlarmDef) -> XResult<()> {
+        def.check()?;
+
+        let value = {
+            let devices = self.devices.lock().await;
+            let (_, dev) = devices.get(&def.device).ok_or_else(|| {
+                XError::new(
+                    XErrorKind::DeviceError,
+                    &format!("{} not found", def.device),
+                )
+            })?;
+            dev.get_values(&def.table, std::slice::from_ref(&def.tag))?
+                .pop()
+                .map(|info| info.value)
+        };
+
+        if self.alarms.lock().unwrap().contains_key(&def.name) {
+            return Err(XError::new(
+                XErrorKind::AlarmError,
+                &format!("{} already exists", def.name),
+            ));
+        }
+        DBAlarm::add(&self.db, &def).await?;
+
+        // start from the current value
+        let mut alarm = Alarm::new(def);
+        let event = value.and_then(|value| alarm.update(&value, Instant::now()));
+        if alarm.deadline().is_some() {
+            self.alarm_delay.notify_one();
+        }
+        self.alarms
+            .lock()
+            .unwrap()
+            .insert(alarm.def().name.clone(), alarm);
+        if let Some(event) = event {
+            self.record_alarm(&event).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn del_alarm<'a>(&'a self, name: &'a str) -> XResult<Option<&'a str>> {
+        DBAlarm::delete(&self.db, name).await?;
+
+        let removed = self.alarms.lock().unwrap().remove(name);
+        Ok(removed.map(|_| name))
+    }
+
+    pub async fn ack_alarm(&self, name: &str) -> XResult<()> {
+        let event = {
+            let mut alarms = self.alarms.lock().unwrap();
+            let alarm = alarms
+                .get_mut(name)
+                .ok_or_else(|| XError::new(XErrorKind::AlarmError, &format!("{name} not found")))?;
+            alarm.acknowledge()?
+        };
+
+        self.record_alarm(&event).await;
+        Ok(())
+    }
+
+    // State changes between the timestamps, in milliseconds; up to now by
+    // default.
+    pub async fn alarm_history(
+        &self,
+        name: Option<&str>,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: usize,
+    ) -> XResult<Vec<AlarmEvent>> {
+        let to = to.unwrap_or_else(|| Utc::now().timestamp_millis());
+        DBAlarm::history(&self.db, name, from.unwrap_or(0), to, limit).await
+    }
+
+    async fn record_alarm(&self, event: &AlarmEvent) {
+        if let Err(err) = DBAlarm::record(&self.db, event).await {
+            warn!("record alarm {}, {err}", event.name);
+        }
+    }
+
+    // Runs the alarms of every changed tag, and completes delayed changes of
+    // tags that keep their value.
+    async fn watch_alarms(
+        mgr: Weak<DeviceMgr>,
+        mut events: broadcast::Receiver<TagEvent>,
+        delay: Arc<Notify>,
+    ) {
+        while let Some(deadline) = mgr.upgrade().map(|mgr| mgr.alarm_deadline()) {
+            let event = tokio::select! {
+                event = events.recv() => Some(event),
+                _ = Self::sleep_until(deadline) => None,
+                // the deadline is taken again
+                _ = delay.notified() => continue,
+            };
+            let Some(mgr) = mgr.upgrade() else {
+                break;
+            };
+
+            let now = Instant::now();
+            let changes: Vec<AlarmEvent> = match event {
+                Some(Ok(event)) => mgr
+                    .alarms
+                    .lock()
+                    .unwrap()
+                    .values_mut()
+                    .filter(|alarm| {
+                        let def = alarm.def();
+                        def.device == event.device
+                            && def.table == event.table
+                            && def.tag == event.name
+                    })
+                    .filter_map(|alarm| alarm.update(&event.value, now))
+                    .collect(),
+                Some(Err(RecvError::Lagged(n))) => {
+                    warn!("alarms lagged, {n} tag changes skipped");
+                    continue;
+                }
+                Some(Err(RecvError::Closed)) => break,
+                None => mgr
+                    .alarms
+                    .lock()
+                    .unwrap()
+                    .values_mut()
+                    .filter_map(|alarm| alarm.expire(now))
+                    .collect(),
+            };
+            for change in changes {
+                mgr.record_alarm(&change).await;
+            }
+        }
+    }
+
+    fn alarm_deadline(&self) -> Option<Instant> {
+        self.alarms
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|alarm| alarm.deadline())
+            .min()
+    }
+
+    async fn sleep_until(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    // Recorded values of tags of a table between `range`, from and to in
+    // milliseconds, aggregated per `bucket` milliseconds unless raw values are
+    // asked for.
+    pub async fn history(
+        &self,
+        device: &str,
+        table: &str,
+        tags: &[String],
+        range: (Option<i64>, Option<i64>),
+        aggregate: Aggregate,
+        bucket: Option<i64>,
+    ) -> XResult<Vec<TagHistory>> {
+        let bucket = match (aggregate, bucket) {
+            (Aggregate::Raw, _) => 0,
+            (_, Some(bucket)) if bucket > 0 => bucket,
+            _ => {
+                return Err(XError::new(
+                    XErrorKind::ParameterError,
+                    "aggregate needs a positive bucket",
+                ))
+            }
+        };
+        let from = range.0.unwrap_or(0);
+        let to = range.1.unwrap_or_else(|| Utc::now().timestamp_millis());
+
+        let mut points: HashMap<&str, Vec<Point>> = HashMap::new();
+        for sample in DBHistory::select(&self.db, device, table, tags, from, to).await? {
+            if let Some(name) = tags.iter().find(|name| **name == sample.tag) {
+                points.entry(name).or_default().push(Point {
+                    time: sample.timestamp,
+                    value: sample.value,
+                });
+            }
+        }
+
+        Ok(tags
+            .iter()
+            .map(|name| TagHistory {
+                name: name.clone(),
+                points: historian::aggregate(
+                    points.get(name.as_str()).map_or(&[], |p| p.as_slice()),
+                    aggregate,
+                    from,
+                    bucket,
+                ),
+            })
+            .collect())
+    }
+
+    async fn flush_history(&self, events: Vec<TagEvent>) {
+        let records: Vec<HistoryRecord> = {
+            let devices = self.devices.lock().await;
+            events
+                .iter()
+                .filter_map(|event| {
+                    let (_, dev) = devices.get(&event.device)?;
+                    let retention = dev.retention(&event.table, &event.name)?;
+                    HistoryRecord::new(event, retention)
+                })
+                .collect()
+        };
+
+        if let Err(err) = DBHistory::add(&self.db, &records).await {
+            warn!("record history, {err}");
+        }
+    }
+
+    // Keeps the changes of tags with a retention.
+    async fn record_history(mgr: Weak<DeviceMgr>, mut events: broadcast::Receiver<TagEvent>) {
+        let mut flush = time::interval(HISTORY_FLUSH);
+        flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut purged = Instant::now();
+        let mut pending = Vec::new();
+
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => pending.push(event),
+                    Err(RecvError::Lagged(n)) => warn!("history lagged, {n} tag changes skipped"),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = flush.tick() => {
+                    let Some(mgr) = mgr.upgrade() else {
+                        break;
+                    };
+                    if !pending.is_empty() {
+                        mgr.flush_history(std::mem::take(&mut pending)).await;
+                    }
+                    if purged.elapsed() >= HISTORY_PURGE {
+                        purged = Instant::now();
+                        if let Err(err) = DBHistory::purge(&mgr.db).await {
+                            warn!("purge history, {err}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     fn create_device(
         &self,
         name: &str,
         driver: &str,
         setting: &Option<Setting>,
     ) -> XResult<Device> {
-        match driver {
-            "Modbus TCP" => {
-                let d = ModbusTcp::new();
-                if let Some(setting) = setting {
-                    d.setting(setting)?;
-                }
-                let device = Device::new(name, Box::new(d), setting)?;
-                Ok(device)
-            }
-            _ => Err(XError::new(
-                XErrorKind::DriverError,
-                &format!("driver not found: {driver}"),
-            )),
-        }
+        let driver = self.drivers.create(driver)?;
+        Device::new(name, driver, setting, self.events.clone())
     }
 
     async fn load(&self) -> XResult<()> {
@@ -281,10 +1104,20 @@ impl DeviceMgr {
         for device in de {
             let d = self.create_device(&device.name, &device.driver, &device.setting)?;
             let id = device.id.unwrap().id.to_string();
+            if device.enabled {
+                if let Err(err) = d.set_enabled(true).await {
+                    warn!("start {}, {err}", device.name);
+                }
+            }
 
             let tables = DBTable::select(&self.db, &device.name).await?;
             for table in tables {
-                d.add_table(&table.name, table.description, &table.parameter)?;
+                d.add_table(
+                    &table.name,
+                    table.description,
+                    &table.parameter,
+                    table.retention,
+                )?;
 
                 let tags = DBTag::select(&self.db, &device.name, &table.name).await;
 
@@ -297,6 +1130,27 @@ impl DeviceMgr {
             ids.insert(id, device.name);
         }
 
+        let defs = DBAlarm::select(&self.db).await?;
+        self.alarms.lock().unwrap().extend(
+            defs.into_iter()
+                .map(|def| (def.name.clone(), Alarm::new(def))),
+        );
+
+        // expressions may read devices loaded after their own
+        let mut targets = Vec::new();
+        for (name, (_, dev)) in devices.iter() {
+            for table in dev.get_tables(None) {
+                for tag in dev.get_tags(&table.name, None).unwrap_or_default() {
+                    if tag.expression.is_some() {
+                        targets.push(TagRef::new(name, &table.name, &tag.name));
+                    }
+                }
+            }
+        }
+        let targets =
+            Self::register_formulas(&devices, &mut self.computed.lock().unwrap(), &targets);
+        self.evaluate(&devices, &targets);
+
         Ok(())
     }
 }
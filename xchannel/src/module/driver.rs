@@ -1,9 +1,11 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde_derive::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 
-use crate::error::XResult;
+use crate::error::{XError, XErrorKind, XResult};
 
-use super::tag::Tag as MTag;
+use super::tag::{QualityReason, Tag as MTag, TagValue};
 use super::value::{DataType, SimpleValue, Value};
 
 #[derive(Debug, Clone, Serialize)]
@@ -11,6 +13,16 @@ pub struct DriverInfo {
     pub name: String,
     pub description: String,
     pub version: String,
+    pub capabilities: Capabilities,
+}
+
+// Which of the runtime operations a driver supports.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Capabilities {
+    pub read: bool,
+    pub write: bool,
+    // pushes changes instead of being polled
+    pub subscribe: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,17 +33,70 @@ pub struct Parameter {
 
 pub type Setting = Vec<Parameter>;
 
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub enum LinkState {
+    Connecting,
+    Connected,
+    Backoff,
+    Disabled,
+}
+
+// Requests sent to the device since the driver was set up. Errors count every
+// failed request, timeouts included.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DriverStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    pub last_error: Option<String>,
+    pub last_success: Option<DateTime<Utc>>,
+}
+
 pub trait Validate {
     fn table_parameter(&self, parameter: &Parameter) -> XResult<()>;
 	fn tag(&self, tags: &[Tag]) -> XResult<()>;
 }
 
+// Changes pushed by a driver for the subscribed tags, by tag name.
+pub type Updates = mpsc::UnboundedReceiver<(String, TagValue)>;
+
 #[async_trait]
 pub trait Driver: Validate {
     fn info(&self) -> DriverInfo;
+    // Checks and keeps the setting; it takes effect on the next `start`.
     fn setting(&self, setting: &Setting) -> XResult<()>;
-    //fn validate(&self, tags: Vec<Tag>) -> XResult<()>;
-    //fn setting(&self, parameters: &[dto::Parameter]) -> XResult<()>;
+    // Opens the link to the device with the current setting.
+    async fn start(&self) -> XResult<()> {
+        Ok(())
+    }
+    // Drops the link to the device until the next `start`.
+    async fn stop(&self) {}
+    fn link_state(&self) -> LinkState {
+        LinkState::Disabled
+    }
+    fn stats(&self) -> DriverStats {
+        DriverStats::default()
+    }
+    // One record per tag, in the order of `tags`.
+    async fn read(&self, tags: &[Tag]) -> Vec<TagValue> {
+        tags.iter()
+            .map(|tag| TagValue::bad(tag.value.clone(), QualityReason::ConfigError))
+            .collect()
+    }
+    async fn write(&self, tag: &Tag, _value: &Value) -> XResult<()> {
+        Err(XError::new(
+            XErrorKind::DriverError,
+            &format!("{} does not support writing {}", self.info().name, tag.name),
+        ))
+    }
+    // Sends the changes of `tags` until the driver stops or the receiver is
+    // dropped.
+    async fn subscribe(&self, _tags: &[Tag]) -> XResult<Updates> {
+        Err(XError::new(
+            XErrorKind::DriverError,
+            &format!("{} does not support subscriptions", self.info().name),
+        ))
+    }
 }
 
 pub struct Tag {
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use super::tag::TagValue;
+
+// Events a slow subscriber may fall behind before it starts losing them.
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagEvent {
+    pub device: String,
+    pub table: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub value: TagValue,
+}
+
+// Fan-out of tag value changes to every subscriber.
+#[derive(Debug, Clone)]
+pub struct Events {
+    sender: broadcast::Sender<TagEvent>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Events { sender }
+    }
+}
+
+impl Events {
+    pub fn subscribe(&self) -> broadcast::Receiver<TagEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, event: TagEvent) {
+        // nobody listening is not an error
+        let _ = self.sender.send(event);
+    }
+}
+
+// `device/table/tag` where each part may use `*` and `?` wildcards; missing
+// parts match everything.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    device: String,
+    table: String,
+    tag: String,
+}
+
+impl From<&str> for Pattern {
+    fn from(pattern: &str) -> Self {
+        let mut parts = pattern.splitn(3, '/').map(|part| part.to_string());
+        let mut next = || parts.next().unwrap_or_else(|| "*".to_string());
+
+        Pattern {
+            device: next(),
+            table: next(),
+            tag: next(),
+        }
+    }
+}
+
+impl Pattern {
+    pub fn matches(&self, device: &str, table: &str, tag: &str) -> bool {
+        glob(&self.device, device) && glob(&self.table, table) && glob(&self.tag, tag)
+    }
+}
+
+fn glob(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text it was matched against
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // let the last `*` swallow one more character
+                Some((sp, st)) => {
+                    star = Some((sp, st + 1));
+                    p = sp + 1;
+                    t = st + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    #[test]
+    fn wildcards() {
+        let pattern = Pattern::from("plc?/*/temp*");
+        assert!(pattern.matches("plc1", "table", "temp"));
+        assert!(pattern.matches("plc2", "", "temp_high"));
+        assert!(!pattern.matches("plc10", "table", "temp"));
+        assert!(!pattern.matches("plc1", "table", "pressure"));
+
+        let pattern = Pattern::from("plc1");
+        assert!(pattern.matches("plc1", "any", "tag"));
+        assert!(!pattern.matches("plc2", "any", "tag"));
+
+        assert!(Pattern::from("*/*/*a*b").matches("d", "t", "xaybzb"));
+        assert!(!Pattern::from("*/*/*a*b").matches("d", "t", "xaybz"));
+    }
+}
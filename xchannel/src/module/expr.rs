@@ -0,0 +1,397 @@
+use crate::error::*;
+
+// Arithmetic over tag values. Tags are referenced as `tag`, `table.tag` or
+// `device.table.tag`; booleans evaluate as 1 and 0.
+//
+//   expr    := or
+//   or      := and ("||" and)*
+//   and     := compare ("&&" compare)*
+//   compare := sum (("==" | "!=" | "<" | "<=" | ">" | ">=") sum)?
+//   sum     := product (("+" | "-") product)*
+//   product := unary (("*" | "/" | "%") unary)*
+//   unary   := ("-" | "!") unary | primary
+//   primary := number | "true" | "false" | path | func "(" expr ("," expr)* ")" | "(" expr ")"
+#[derive(Debug, Clone)]
+pub struct Expr {
+    node: Node,
+    refs: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f64),
+    Ref(usize),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Func {
+    Abs,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Path(Vec<String>),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn syntax(msg: &str) -> XError {
+    XError::new(XErrorKind::TagError, &format!("invalid expression, {msg}"))
+}
+
+fn tokenize(src: &str) -> XResult<Vec<Token>> {
+    const OPS: [&str; 15] = [
+        "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "=",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = src.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+                .unwrap_or(rest.len());
+            // exponent sign, as in 1e-3
+            let len = if rest[..len].ends_with(['e', 'E']) && rest[len..].starts_with(['+', '-']) {
+                let exponent = &rest[len + 1..];
+                len + 1
+                    + exponent
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(exponent.len())
+            } else {
+                len
+            };
+            let number = rest[..len]
+                .parse()
+                .map_err(|_| syntax(&format!("bad number {}", &rest[..len])))?;
+            tokens.push(Token::Number(number));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            let path: Vec<String> = rest[..len].split('.').map(|s| s.to_string()).collect();
+            if path.len() > 3 || path.iter().any(|s| s.is_empty()) {
+                return Err(syntax(&format!("bad reference {}", &rest[..len])));
+            }
+            tokens.push(Token::Path(path));
+            len
+        } else if c == '(' || c == ')' || c == ',' {
+            tokens.push(match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                _ => Token::Comma,
+            });
+            1
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            if *op == "=" {
+                return Err(syntax("use == to compare"));
+            }
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            return Err(syntax(&format!("unexpected {c}")));
+        };
+
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    refs: Vec<Vec<String>>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> XResult<()> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            _ => Err(syntax(&format!("expected {token:?}"))),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&'static str],
+        operand: fn(&mut Self) -> XResult<Node>,
+    ) -> XResult<Node> {
+        let mut node = operand(self)?;
+        while let Some(op) = self.eat_op(ops) {
+            node = Node::Binary(op_of(op), Box::new(node), Box::new(operand(self)?));
+        }
+        Ok(node)
+    }
+
+    fn or(&mut self) -> XResult<Node> {
+        self.binary(&["||"], Self::and)
+    }
+
+    fn and(&mut self) -> XResult<Node> {
+        self.binary(&["&&"], Self::compare)
+    }
+
+    fn compare(&mut self) -> XResult<Node> {
+        let node = self.sum()?;
+        match self.eat_op(&["==", "!=", "<", "<=", ">", ">="]) {
+            Some(op) => Ok(Node::Binary(
+                op_of(op),
+                Box::new(node),
+                Box::new(self.sum()?),
+            )),
+            None => Ok(node),
+        }
+    }
+
+    fn sum(&mut self) -> XResult<Node> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> XResult<Node> {
+        self.binary(&["*", "/", "%"], Self::unary)
+    }
+
+    fn unary(&mut self) -> XResult<Node> {
+        match self.eat_op(&["-", "!"]) {
+            Some("-") => Ok(Node::Neg(Box::new(self.unary()?))),
+            Some(_) => Ok(Node::Not(Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> XResult<Node> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Node::Number(n)),
+            Some(Token::LParen) => {
+                let node = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(node)
+            }
+            Some(Token::Path(path)) if self.peek() == Some(&Token::LParen) => {
+                let func = match path.join(".").as_str() {
+                    "abs" => Func::Abs,
+                    "min" => Func::Min,
+                    "max" => Func::Max,
+                    name => return Err(syntax(&format!("unknown function {name}"))),
+                };
+                self.pos += 1;
+
+                let mut args = vec![self.or()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    args.push(self.or()?);
+                }
+                self.expect(Token::RParen)?;
+
+                match (func, args.len()) {
+                    (Func::Abs, 1) | (Func::Min | Func::Max, 2..) => Ok(Node::Call(func, args)),
+                    _ => Err(syntax(&format!("wrong number of arguments to {func:?}"))),
+                }
+            }
+            Some(Token::Path(path)) if path == ["true"] => Ok(Node::Number(1.0)),
+            Some(Token::Path(path)) if path == ["false"] => Ok(Node::Number(0.0)),
+            Some(Token::Path(path)) => {
+                let index = match self.refs.iter().position(|r| *r == path) {
+                    Some(index) => index,
+                    None => {
+                        self.refs.push(path);
+                        self.refs.len() - 1
+                    }
+                };
+                Ok(Node::Ref(index))
+            }
+            Some(token) => Err(syntax(&format!("unexpected {token:?}"))),
+            None => Err(syntax("unexpected end")),
+        }
+    }
+}
+
+fn op_of(op: &str) -> Op {
+    match op {
+        "||" => Op::Or,
+        "&&" => Op::And,
+        "==" => Op::Eq,
+        "!=" => Op::Ne,
+        "<" => Op::Lt,
+        "<=" => Op::Le,
+        ">" => Op::Gt,
+        ">=" => Op::Ge,
+        "+" => Op::Add,
+        "-" => Op::Sub,
+        "*" => Op::Mul,
+        "/" => Op::Div,
+        _ => Op::Rem,
+    }
+}
+
+fn truth(v: f64) -> bool {
+    v != 0.0
+}
+
+fn eval(node: &Node, inputs: &[f64]) -> f64 {
+    match node {
+        Node::Number(n) => *n,
+        Node::Ref(index) => inputs[*index],
+        Node::Neg(node) => -eval(node, inputs),
+        Node::Not(node) => f64::from(!truth(eval(node, inputs))),
+        Node::Binary(op, lhs, rhs) => {
+            let (l, r) = (eval(lhs, inputs), eval(rhs, inputs));
+            match op {
+                Op::Or => f64::from(truth(l) || truth(r)),
+                Op::And => f64::from(truth(l) && truth(r)),
+                Op::Eq => f64::from(l == r),
+                Op::Ne => f64::from(l != r),
+                Op::Lt => f64::from(l < r),
+                Op::Le => f64::from(l <= r),
+                Op::Gt => f64::from(l > r),
+                Op::Ge => f64::from(l >= r),
+                Op::Add => l + r,
+                Op::Sub => l - r,
+                Op::Mul => l * r,
+                Op::Div => l / r,
+                Op::Rem => l % r,
+            }
+        }
+        Node::Call(func, args) => {
+            let mut args = args.iter().map(|arg| eval(arg, inputs));
+            match func {
+                Func::Abs => args.next().unwrap_or_default().abs(),
+                Func::Min => args.fold(f64::INFINITY, f64::min),
+                Func::Max => args.fold(f64::NEG_INFINITY, f64::max),
+            }
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> XResult<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+            refs: Vec::new(),
+        };
+
+        let node = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(syntax(&format!("unexpected {token:?}")));
+        }
+
+        Ok(Expr {
+            node,
+            refs: parser.refs,
+        })
+    }
+
+    // Referenced tags, as written.
+    pub fn refs(&self) -> &[Vec<String>] {
+        &self.refs
+    }
+
+    // `inputs` holds the value of each of `refs`, in order.
+    pub fn eval(&self, inputs: &[f64]) -> f64 {
+        eval(&self.node, inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expr;
+
+    fn eval(src: &str, inputs: &[f64]) -> f64 {
+        Expr::parse(src).unwrap().eval(inputs)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("1 + 2 * 3", &[]), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", &[]), 9.0);
+        assert_eq!(eval("-2 - -3", &[]), 1.0);
+        assert_eq!(eval("7 % 4 / 2", &[]), 1.5);
+        assert_eq!(eval("1.5e1 + 1e-1", &[]), 15.1);
+        assert_eq!(eval("max(1, 5, 3) + min(2, -1) + abs(-4)", &[]), 8.0);
+        assert_eq!(eval("1 < 2 && !(3 >= 4) || false", &[]), 1.0);
+    }
+
+    #[test]
+    fn references() {
+        let expr = Expr::parse("tank1.level * 0.3 + pump.flow - tank1.level").unwrap();
+        assert_eq!(
+            expr.refs(),
+            [
+                vec!["tank1".to_string(), "level".to_string()],
+                vec!["pump".to_string(), "flow".to_string()],
+            ]
+        );
+        assert_eq!(expr.eval(&[10.0, 2.0]), -5.0);
+        assert_eq!(Expr::parse("plc.tank.level").unwrap().refs()[0].len(), 3);
+    }
+
+    #[test]
+    fn rejects() {
+        for src in [
+            "",
+            "1 +",
+            "(1",
+            "1)",
+            "a = 1",
+            "a.b.c.d",
+            "a..b",
+            "f(1)",
+            "abs(1, 2)",
+            "1 2",
+            "#",
+        ] {
+            assert!(Expr::parse(src).is_err(), "{src}");
+        }
+    }
+}
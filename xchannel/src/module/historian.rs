@@ -0,0 +1,123 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::event::TagEvent;
+use super::tag::Quality;
+
+// A stored sample of a tag. `expires` is when retention drops it, both in
+// milliseconds since the epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub device: String,
+    pub table: String,
+    pub tag: String,
+    pub value: f64,
+    pub timestamp: i64,
+    pub expires: i64,
+}
+
+impl HistoryRecord {
+    // Sample of a change kept for `retention` seconds. Only numeric values
+    // read with good or uncertain quality are kept.
+    pub fn new(event: &TagEvent, retention: u64) -> Option<Self> {
+        if event.value.quality == Quality::Bad {
+            return None;
+        }
+
+        let value = event.value.value.as_f64()?;
+        let time = event.value.source_time.or(event.value.update_time)?;
+        let timestamp = time.timestamp_millis();
+
+        Some(HistoryRecord {
+            device: event.device.clone(),
+            table: event.table.clone(),
+            tag: event.name.clone(),
+            value,
+            timestamp,
+            expires: timestamp.saturating_add(retention.saturating_mul(1000) as i64),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    Raw,
+    Min,
+    Max,
+    Avg,
+    Last,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Point {
+    pub time: i64,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagHistory {
+    pub name: String,
+    pub points: Vec<Point>,
+}
+
+// Folds time-ordered points into one per `bucket` milliseconds counted from
+// `from`, stamped with the bucket start. Empty buckets are left out.
+pub fn aggregate(points: &[Point], aggregate: Aggregate, from: i64, bucket: i64) -> Vec<Point> {
+    if aggregate == Aggregate::Raw || bucket <= 0 {
+        return points.to_vec();
+    }
+
+    let mut result: Vec<Point> = Vec::new();
+    let mut count = 0;
+
+    for point in points {
+        let time = from + (point.time - from).div_euclid(bucket) * bucket;
+
+        match result.last_mut() {
+            Some(last) if last.time == time => {
+                count += 1;
+                last.value = match aggregate {
+                    Aggregate::Min => last.value.min(point.value),
+                    Aggregate::Max => last.value.max(point.value),
+                    // running mean
+                    Aggregate::Avg => last.value + (point.value - last.value) / count as f64,
+                    Aggregate::Last | Aggregate::Raw => point.value,
+                };
+            }
+            _ => {
+                count = 1;
+                result.push(Point {
+                    time,
+                    value: point.value,
+                });
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate, Aggregate, Point};
+
+    #[test]
+    fn buckets() {
+        let points: Vec<Point> = [(1000, 1.0), (1500, 3.0), (1900, 2.0), (3100, 5.0)]
+            .iter()
+            .map(|&(time, value)| Point { time, value })
+            .collect();
+        let values = |agg| -> Vec<(i64, f64)> {
+            aggregate(&points, agg, 1000, 1000)
+                .iter()
+                .map(|p| (p.time, p.value))
+                .collect()
+        };
+
+        assert_eq!(values(Aggregate::Min), [(1000, 1.0), (3000, 5.0)]);
+        assert_eq!(values(Aggregate::Max), [(1000, 3.0), (3000, 5.0)]);
+        assert_eq!(values(Aggregate::Avg), [(1000, 2.0), (3000, 5.0)]);
+        assert_eq!(values(Aggregate::Last), [(1000, 2.0), (3000, 5.0)]);
+        assert_eq!(aggregate(&points, Aggregate::Raw, 1000, 1000), points);
+    }
+}
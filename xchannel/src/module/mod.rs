@@ -1,7 +1,13 @@
+pub mod alarm;
+pub mod computed;
 pub mod db;
 pub mod device;
 pub mod device_manager;
 pub mod driver;
+pub mod event;
+pub mod expr;
+pub mod historian;
+pub mod registry;
 pub mod table;
 pub mod tag;
 pub mod value;
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::error::*;
+
+use super::driver::{Driver, DriverInfo};
+
+// Makes the drivers of one kind, one per device.
+pub trait DriverFactory: Send + Sync {
+    fn info(&self) -> DriverInfo;
+    fn create(&self) -> Arc<dyn Driver + Send + Sync>;
+}
+
+// Factory of drivers that start out unconfigured from `Default`.
+pub struct DefaultFactory<D>(PhantomData<fn() -> D>);
+
+impl<D> Default for DefaultFactory<D> {
+    fn default() -> Self {
+        DefaultFactory(PhantomData)
+    }
+}
+
+impl<D: Driver + Default + Send + Sync + 'static> DriverFactory for DefaultFactory<D> {
+    fn info(&self) -> DriverInfo {
+        D::default().info()
+    }
+
+    fn create(&self) -> Arc<dyn Driver + Send + Sync> {
+        Arc::new(D::default())
+    }
+}
+
+// Drivers available to devices, by the name in their `DriverInfo`.
+#[derive(Default)]
+pub struct Registry {
+    factories: HashMap<String, (DriverInfo, Box<dyn DriverFactory>)>,
+}
+
+impl Registry {
+    pub fn register(&mut self, factory: Box<dyn DriverFactory>) {
+        let info = factory.info();
+        self.factories.insert(info.name.clone(), (info, factory));
+    }
+
+    pub fn register_default<D: Driver + Default + Send + Sync + 'static>(&mut self) {
+        self.register(Box::<DefaultFactory<D>>::default());
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    pub fn infos(&self) -> Vec<DriverInfo> {
+        self.factories
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect()
+    }
+
+    pub fn create(&self, name: &str) -> XResult<Arc<dyn Driver + Send + Sync>> {
+        self.factories
+            .get(name)
+            .map(|(_, factory)| factory.create())
+            .ok_or_else(|| {
+                XError::new(
+                    XErrorKind::DriverError,
+                    &format!("driver not found: {name}"),
+                )
+            })
+    }
+}
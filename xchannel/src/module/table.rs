@@ -1,18 +1,44 @@
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::future;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
 
 use crate::error::*;
 
-use super::driver::Parameter;
-use super::tag::Tag;
+use super::driver::{Driver, Parameter, Tag as DTag, Updates};
+use super::event::{Events, TagEvent};
+use super::tag::{Quality, QualityReason, Tag, TagInfo, TagValue};
+use super::value::SimpleValue;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+// A good value not refreshed for this many intervals is reported as stale.
+const STALE_INTERVALS: u32 = 3;
+
+#[derive(Debug)]
+struct Entry {
+    tag: Tag,
+    value: TagValue,
+    // record last published and when
+    published: Option<(TagValue, Instant)>,
+}
 
 #[derive(Debug)]
 pub struct Table {
     name: String,
     description: Option<String>,
     parameter: Parameter,
-    tags: Mutex<HashMap<String, Tag>>,
+    // seconds of history kept for tags without their own retention
+    retention: Option<u64>,
+    tags: Mutex<HashMap<String, Entry>>,
+    poller: Mutex<Option<JoinHandle<()>>>,
+    // end of the last read where every tag came back good
+    last_poll: Mutex<Option<DateTime<Utc>>>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -20,15 +46,260 @@ pub struct TableInfo {
     pub name: String,
     pub description: Option<String>,
     pub parameter: Parameter,
+    pub retention: Option<u64>,
 }
 
 impl Table {
-    pub fn new(name: String, description: Option<String>, parameter: Parameter) -> Self {
+    pub fn new(
+        name: String,
+        description: Option<String>,
+        parameter: Parameter,
+        retention: Option<u64>,
+    ) -> Self {
         Table {
             name,
             description,
             parameter,
+            retention,
             tags: Mutex::new(HashMap::new()),
+            poller: Mutex::new(None),
+            last_poll: Mutex::new(None),
+        }
+    }
+
+    // Poll interval from the `interval` parameter, in milliseconds.
+    pub fn interval(&self) -> Duration {
+        match (self.parameter.option.as_str(), &self.parameter.value) {
+            ("interval", SimpleValue::INT(ms)) if *ms > 0 => Duration::from_millis(*ms as u64),
+            _ => DEFAULT_INTERVAL,
+        }
+    }
+
+    // Starts reading the tags through the driver every interval, or taking the
+    // changes it pushes when it supports subscriptions, and publishes them as
+    // events of `device`. The loop only holds a weak reference, so it ends
+    // once the table is dropped.
+    pub fn start(
+        self: &Arc<Self>,
+        driver: Arc<dyn Driver + Send + Sync>,
+        device: String,
+        events: Events,
+    ) {
+        let capabilities = driver.info().capabilities;
+        let table = Arc::downgrade(self);
+        let poller = if capabilities.subscribe {
+            tokio::spawn(Self::listen(table, driver, self.interval(), device, events))
+        } else if capabilities.read {
+            tokio::spawn(Self::poll(table, driver, self.interval(), device, events))
+        } else {
+            self.stop();
+            return;
+        };
+
+        if let Some(previous) = self.poller.lock().unwrap().replace(poller) {
+            previous.abort();
+        }
+    }
+
+    pub fn stop(&self) {
+        if let Some(poller) = self.poller.lock().unwrap().take() {
+            poller.abort();
+        }
+    }
+
+    pub fn last_poll(&self) -> Option<DateTime<Utc>> {
+        *self.last_poll.lock().unwrap()
+    }
+
+    // Marks the polled tags bad for `reason`; returns the changes to publish.
+    pub fn set_bad(&self, reason: QualityReason) -> Vec<(String, TagValue)> {
+        let (names, tags) = self.polled_tags();
+        let values = tags
+            .into_iter()
+            .map(|tag| TagValue::bad(tag.value, reason))
+            .collect();
+
+        self.update_values(&names, values)
+    }
+
+    async fn poll(
+        table: Weak<Table>,
+        driver: Arc<dyn Driver + Send + Sync>,
+        period: Duration,
+        device: String,
+        events: Events,
+    ) {
+        let mut ticker = time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            let Some(table) = table.upgrade() else {
+                break;
+            };
+            let (names, tags) = table.polled_tags();
+            if tags.is_empty() {
+                continue;
+            }
+
+            let values = driver.read(&tags).await;
+            table.publish(&names, values, &device, &events);
+        }
+    }
+
+    // Takes the changes the driver pushes for the tags, subscribing again
+    // whenever the set of tags changes or the driver ends the subscription.
+    async fn listen(
+        table: Weak<Table>,
+        driver: Arc<dyn Driver + Send + Sync>,
+        period: Duration,
+        device: String,
+        events: Events,
+    ) {
+        let mut ticker = time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut subscribed: Vec<String> = Vec::new();
+        let mut updates: Option<Updates> = None;
+
+        loop {
+            let update = async {
+                match updates.as_mut() {
+                    Some(updates) => updates.recv().await,
+                    None => future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let Some(table) = table.upgrade() else {
+                        break;
+                    };
+                    let (mut names, tags) = table.polled_tags();
+                    names.sort();
+                    if updates.is_some() && names == subscribed {
+                        continue;
+                    }
+
+                    updates = None;
+                    subscribed.clear();
+                    if tags.is_empty() {
+                        continue;
+                    }
+                    match driver.subscribe(&tags).await {
+                        Ok(receiver) => {
+                            updates = Some(receiver);
+                            subscribed = names;
+                        }
+                        Err(err) => warn!("subscribe {device}/{}, {err}", table.name()),
+                    }
+                }
+                update = update => {
+                    let Some(table) = table.upgrade() else {
+                        break;
+                    };
+                    match update {
+                        Some((name, value)) => table.publish(&[name], vec![value], &device, &events),
+                        // ended by the driver
+                        None => updates = None,
+                    }
+                }
+            }
+        }
+    }
+
+    // Stores the values read and publishes the changes.
+    fn publish(&self, names: &[String], values: Vec<TagValue>, device: &str, events: &Events) {
+        if values.iter().all(|value| value.quality == Quality::Good) {
+            *self.last_poll.lock().unwrap() = Some(Utc::now());
+        }
+        for (name, value) in self.update_values(names, values) {
+            events.publish(TagEvent {
+                device: device.to_string(),
+                table: self.name(),
+                name,
+                value,
+            });
+        }
+    }
+
+    // Tags with a device address, as the driver sees them.
+    fn polled_tags(&self) -> (Vec<String>, Vec<DTag>) {
+        let tags = self.tags.lock().unwrap();
+
+        tags.values()
+            .filter(|entry| entry.tag.address.is_some())
+            .map(|entry| (entry.tag.name.clone(), DTag::from(&entry.tag)))
+            .unzip()
+    }
+
+    // Stores the new records and returns those that pass the tag filter.
+    pub fn update_values(
+        &self,
+        names: &[String],
+        values: Vec<TagValue>,
+    ) -> Vec<(String, TagValue)> {
+        let mut tags = self.tags.lock().unwrap();
+        let mut changes = Vec::new();
+        let now = Instant::now();
+
+        for (name, value) in names.iter().zip(values) {
+            // gone if deleted while the read was in flight
+            let Some(entry) = tags.get_mut(name) else {
+                continue;
+            };
+
+            let value = match value.quality {
+                Quality::Good => TagValue {
+                    value: entry.tag.scaled(&value.value),
+                    ..value
+                },
+                _ => TagValue {
+                    value: entry.value.value.clone(),
+                    source_time: entry.value.source_time,
+                    ..value
+                },
+            };
+            if entry
+                .tag
+                .filter
+                .passes(entry.published.as_ref(), &value, now)
+            {
+                entry.published = Some((value.clone(), now));
+                changes.push((name.clone(), value.clone()));
+            }
+            entry.value = value;
+        }
+
+        changes
+    }
+
+    fn info_of(&self, entry: &Entry) -> TagInfo {
+        let mut value = entry.value.clone();
+
+        let stale_after = self.interval() * STALE_INTERVALS;
+        let age = value
+            .update_time
+            .and_then(|time| (Utc::now() - time).to_std().ok());
+        if entry.tag.address.is_some()
+            && value.quality == Quality::Good
+            && age.is_some_and(|age| age > stale_after)
+        {
+            value.quality = Quality::Uncertain;
+            value.reason = Some(QualityReason::Stale);
+        }
+
+        TagInfo {
+            name: entry.tag.name.clone(),
+            dtype: entry.tag.dtype,
+            address: entry.tag.address.clone(),
+            description: entry.tag.description.clone(),
+            filter: entry.tag.filter,
+            scaling: entry.tag.scaling,
+            unit: entry.tag.unit.clone(),
+            expression: entry.tag.expression.clone(),
+            retention: entry.tag.retention,
+            value,
         }
     }
 
@@ -41,10 +312,21 @@ impl Table {
             name: self.name.to_string(),
             description: self.description.clone(),
             parameter: self.parameter.clone(),
+            retention: self.retention,
         }
     }
 
-    pub fn get_tags(&self, name: Option<String>) -> XResult<Vec<Tag>> {
+    // Seconds the history of a tag is kept, if it is recorded at all. A zero
+    // retention on the tag opts it out of the table setting.
+    pub fn retention(&self, name: &str) -> Option<u64> {
+        let tags = self.tags.lock().unwrap();
+
+        tags.get(name)
+            .and_then(|entry| entry.tag.retention.or(self.retention))
+            .filter(|retention| *retention > 0)
+    }
+
+    pub fn get_tags(&self, name: Option<String>) -> XResult<Vec<TagInfo>> {
         let tags = self.tags.lock().unwrap();
 
         let iter = tags.iter();
@@ -52,13 +334,41 @@ impl Table {
         if let Some(name) = name {
             Ok(iter
                 .filter(|(key, _)| key.contains(&name))
-                .map(|(_, tag)| tag.clone())
+                .map(|(_, entry)| self.info_of(entry))
                 .collect())
         } else {
-            Ok(iter.map(|(_, tag)| tag.clone()).collect())
+            Ok(iter.map(|(_, entry)| self.info_of(entry)).collect())
         }
     }
 
+    // Current values of the named tags, in the given order; all tags when no
+    // name is given.
+    pub fn get_values(&self, names: &[String]) -> XResult<Vec<TagInfo>> {
+        let tags = self.tags.lock().unwrap();
+
+        if names.is_empty() {
+            return Ok(tags.values().map(|entry| self.info_of(entry)).collect());
+        }
+
+        names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                tags.get(name)
+                    .map(|entry| self.info_of(entry))
+                    .ok_or_else(|| XError::TagError(index as i32, format!("{name} not found")))
+            })
+            .collect()
+    }
+
+    pub fn get_tag(&self, name: &str) -> XResult<Tag> {
+        let tags = self.tags.lock().unwrap();
+
+        tags.get(name)
+            .map(|entry| entry.tag.clone())
+            .ok_or_else(|| XError::new(XErrorKind::TagError, &format!("{name} not found")))
+    }
+
     pub fn add_tags(&self, tags: &[Tag]) -> XResult<()> {
         let mut t = self.tags.lock().unwrap();
 
@@ -70,12 +380,62 @@ impl Table {
                 ));
             }
 
-            t.insert(tag.name.clone(), tag.clone());
+            t.insert(
+                tag.name.clone(),
+                Entry {
+                    tag: tag.clone(),
+                    value: TagValue::initial(tag),
+                    published: None,
+                },
+            );
         }
 
         Ok(())
     }
 
+    // Replaces the tag, which may have a new name. The value carries over unless
+    // the way it is read changed.
+    pub fn update_tag(&self, name: &str, tag: Tag) -> XResult<()> {
+        let mut tags = self.tags.lock().unwrap();
+
+        if tag.name != name && tags.contains_key(&tag.name) {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                &format!("conflict name {}", tag.name),
+            ));
+        }
+        let Some(entry) = tags.remove(name) else {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                &format!("{name} not found"),
+            ));
+        };
+
+        let same_source = entry.tag.dtype == tag.dtype
+            && entry.tag.address == tag.address
+            && entry.tag.scaling == tag.scaling
+            && entry.tag.expression == tag.expression;
+        let entry = if same_source {
+            Entry { tag, ..entry }
+        } else {
+            Entry {
+                value: TagValue::initial(&tag),
+                tag,
+                published: None,
+            }
+        };
+        tags.insert(entry.tag.name.clone(), entry);
+
+        Ok(())
+    }
+
+    // A table with new settings that takes the tags over from this one.
+    pub fn replace(&self, info: TableInfo) -> Table {
+        let table = Table::new(info.name, info.description, info.parameter, info.retention);
+        *table.tags.lock().unwrap() = std::mem::take(&mut *self.tags.lock().unwrap());
+        table
+    }
+
     pub fn del_tags(&self, tags: &[String]) -> XResult<()> {
         let mut t = self.tags.lock().unwrap();
 
@@ -86,3 +446,242 @@ impl Table {
         Ok(())
     }
 }
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use chrono::Utc;
+    use tokio::sync::mpsc;
+
+    use crate::error::XResult;
+    use crate::module::driver::{
+        Capabilities, Driver, DriverInfo, Parameter, Setting, Tag as DTag, Updates, Validate,
+    };
+    use crate::module::event::Events;
+    use crate::module::tag::{Filter, Quality, QualityReason, Tag, TagValue};
+    use crate::module::value::{DataType, SimpleValue, Value};
+
+    use super::Table;
+
+    // Reads every tag as the length of its address.
+    struct Counter;
+
+    impl Validate for Counter {
+        fn table_parameter(&self, _parameter: &Parameter) -> XResult<()> {
+            Ok(())
+        }
+
+        fn tag(&self, _tags: &[DTag]) -> XResult<()> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Driver for Counter {
+        fn info(&self) -> DriverInfo {
+            DriverInfo {
+                name: "Counter".to_string(),
+                description: String::new(),
+                version: String::new(),
+                capabilities: Capabilities {
+                    read: true,
+                    ..Default::default()
+                },
+            }
+        }
+
+        fn setting(&self, _setting: &Setting) -> XResult<()> {
+            Ok(())
+        }
+
+        async fn read(&self, tags: &[DTag]) -> Vec<TagValue> {
+            tags.iter()
+                .map(|tag| TagValue::good(Value::UINT16(tag.address.len() as u16), Utc::now()))
+                .collect()
+        }
+    }
+
+    // Pushes whatever is sent through the sender of the last subscription.
+    #[derive(Default)]
+    struct Pusher {
+        sender: Mutex<Option<mpsc::UnboundedSender<(String, TagValue)>>>,
+    }
+
+    impl Validate for Pusher {
+        fn table_parameter(&self, _parameter: &Parameter) -> XResult<()> {
+            Ok(())
+        }
+
+        fn tag(&self, _tags: &[DTag]) -> XResult<()> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Driver for Pusher {
+        fn info(&self) -> DriverInfo {
+            DriverInfo {
+                name: "Pusher".to_string(),
+                description: String::new(),
+                version: String::new(),
+                capabilities: Capabilities {
+                    subscribe: true,
+                    ..Default::default()
+                },
+            }
+        }
+
+        fn setting(&self, _setting: &Setting) -> XResult<()> {
+            Ok(())
+        }
+
+        async fn subscribe(&self, _tags: &[DTag]) -> XResult<Updates> {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            *self.sender.lock().unwrap() = Some(sender);
+            Ok(receiver)
+        }
+    }
+
+    fn tag(name: &str, address: Option<&str>) -> Tag {
+        Tag {
+            name: name.to_string(),
+            value: Value::UINT16(0),
+            dtype: DataType::WORD,
+            address: address.map(|a| a.to_string()),
+            description: None,
+            filter: Filter::default(),
+            scaling: None,
+            unit: None,
+            expression: None,
+            retention: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn poll_updates_values() {
+        let table = Arc::new(Table::new(
+            "table".to_string(),
+            None,
+            Parameter {
+                option: "interval".to_string(),
+                value: SimpleValue::INT(10),
+            },
+            None,
+        ));
+        assert_eq!(table.interval(), Duration::from_millis(10));
+
+        table
+            .add_tags(&[tag("polled", Some("1.4100")), tag("memory", None)])
+            .unwrap();
+
+        let polled = table.get_tags(Some("polled".to_string())).unwrap();
+        assert_eq!(polled[0].value.quality, Quality::Bad);
+        assert_eq!(
+            polled[0].value.reason,
+            Some(QualityReason::WaitingForInitialData)
+        );
+
+        let events = Events::default();
+        let mut receiver = events.subscribe();
+        table.start(Arc::new(Counter), "device".to_string(), events);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // published once, later reads did not change anything
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(
+            (
+                event.device.as_str(),
+                event.table.as_str(),
+                event.name.as_str()
+            ),
+            ("device", "table", "polled")
+        );
+        assert!(receiver.try_recv().is_err());
+
+        let polled = table.get_tags(Some("polled".to_string())).unwrap();
+        assert_eq!(polled[0].value.value, Value::UINT16(6));
+        assert_eq!(polled[0].value.quality, Quality::Good);
+        assert!(polled[0].value.source_time.is_some());
+        let memory = table.get_tags(Some("memory".to_string())).unwrap();
+        assert_eq!(memory[0].value.value, Value::UINT16(0));
+        assert_eq!(memory[0].value.quality, Quality::Good);
+
+        // a failed read keeps the last good value
+        let changes = table.update_values(
+            &["polled".to_string()],
+            vec![TagValue::bad(Value::UINT16(0), QualityReason::CommFailure)],
+        );
+        assert_eq!(changes[0].1.value, Value::UINT16(6));
+        let polled = table.get_tags(Some("polled".to_string())).unwrap();
+        assert_eq!(polled[0].value.value, Value::UINT16(6));
+        assert_eq!(polled[0].value.reason, Some(QualityReason::CommFailure));
+
+        // renamed, the value carries over; a new address starts over
+        table.stop();
+        assert!(table.update_tag("polled", tag("memory", None)).is_err());
+        table
+            .update_tag("polled", tag("renamed", Some("1.4100")))
+            .unwrap();
+        let renamed = table.get_values(&["renamed".to_string()]).unwrap();
+        assert_eq!(renamed[0].value.value, Value::UINT16(6));
+        assert!(table.get_tag("polled").is_err());
+        table
+            .update_tag("renamed", tag("renamed", Some("1.4101")))
+            .unwrap();
+        let renamed = table.get_values(&["renamed".to_string()]).unwrap();
+        assert_eq!(
+            renamed[0].value.reason,
+            Some(QualityReason::WaitingForInitialData)
+        );
+    }
+
+    #[tokio::test]
+    async fn subscription_pushes_values() {
+        let table = Arc::new(Table::new(
+            "table".to_string(),
+            None,
+            Parameter {
+                option: "interval".to_string(),
+                value: SimpleValue::INT(10),
+            },
+            None,
+        ));
+        table.add_tags(&[tag("pushed", Some("1.4100"))]).unwrap();
+
+        let driver = Arc::new(Pusher::default());
+        let events = Events::default();
+        let mut receiver = events.subscribe();
+        table.start(driver.clone(), "device".to_string(), events);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let sender = driver.sender.lock().unwrap().take().unwrap();
+        sender
+            .send((
+                "pushed".to_string(),
+                TagValue::good(Value::UINT16(7), Utc::now()),
+            ))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.name, "pushed");
+        assert_eq!(event.value.value, Value::UINT16(7));
+        let pushed = table.get_values(&["pushed".to_string()]).unwrap();
+        assert_eq!(pushed[0].value.quality, Quality::Good);
+
+        // a new tag is covered by a new subscription
+        table.add_tags(&[tag("added", Some("1.4101"))]).unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(driver.sender.lock().unwrap().is_some());
+        table.stop();
+    }
+}
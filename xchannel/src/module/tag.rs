@@ -1,6 +1,11 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
 use serde_derive::{Deserialize, Serialize};
 
-use super::value::{DataType, Value};
+use crate::error::*;
+
+use super::value::{DataType, Value, ValueType};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tag {
@@ -9,4 +14,353 @@ pub struct Tag {
     pub dtype: DataType,
     pub address: Option<String>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub scaling: Option<Scaling>,
+    #[serde(default)]
+    pub unit: Option<String>,
+    // Computed tags carry an expression over other tags instead of an address.
+    #[serde(default)]
+    pub expression: Option<String>,
+    // Seconds the history of the tag is kept; the table setting applies when
+    // not given.
+    #[serde(default)]
+    pub retention: Option<u64>,
+}
+
+// Conversion from the raw device value to engineering units. Scaled tags read
+// as DOUBLE.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Scaling {
+    // value * k + offset
+    Linear {
+        k: f64,
+        offset: f64,
+    },
+    // raw_low..raw_high mapped onto eng_low..eng_high
+    Range {
+        raw_low: f64,
+        raw_high: f64,
+        eng_low: f64,
+        eng_high: f64,
+    },
+}
+
+impl Scaling {
+    // As `Linear` factors.
+    fn factors(&self) -> (f64, f64) {
+        match *self {
+            Scaling::Linear { k, offset } => (k, offset),
+            Scaling::Range {
+                raw_low,
+                raw_high,
+                eng_low,
+                eng_high,
+            } => {
+                let k = (eng_high - eng_low) / (raw_high - raw_low);
+                (k, eng_low - raw_low * k)
+            }
+        }
+    }
+
+    pub fn check(&self, dtype: DataType) -> XResult<()> {
+        if matches!(
+            ValueType::from(dtype),
+            ValueType::BIT | ValueType::BOOL | ValueType::STRING
+        ) {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                &format!("scaling on non-numeric {dtype:?}"),
+            ));
+        }
+
+        let (k, offset) = self.factors();
+        if !k.is_finite() || !offset.is_finite() || k == 0.0 {
+            return Err(XError::new(XErrorKind::TagError, "invalid scaling"));
+        }
+
+        Ok(())
+    }
+
+    pub fn scale(&self, raw: f64) -> f64 {
+        let (k, offset) = self.factors();
+        raw * k + offset
+    }
+
+    pub fn unscale(&self, eng: f64) -> f64 {
+        let (k, offset) = self.factors();
+        (eng - offset) / k
+    }
+}
+
+impl Tag {
+    pub fn check(&self) -> XResult<()> {
+        if ValueType::from(self.dtype) != self.value.v_type() {
+            return Err(XError::new(XErrorKind::TagError, "Tag value type mismatch"));
+        }
+        if self.address.is_some() && self.expression.is_some() {
+            return Err(XError::new(
+                XErrorKind::TagError,
+                "Tag takes either an address or an expression",
+            ));
+        }
+        if let Some(scaling) = &self.scaling {
+            scaling.check(self.dtype)?;
+        }
+        if !self.filter.is_valid() {
+            return Err(XError::new(XErrorKind::TagError, "invalid deadband"));
+        }
+
+        Ok(())
+    }
+
+    // Engineering value of a raw device value.
+    pub fn scaled(&self, raw: &Value) -> Value {
+        match (&self.scaling, raw.as_f64()) {
+            (Some(scaling), Some(raw)) => Value::DOUBLE(scaling.scale(raw)),
+            _ => raw.clone(),
+        }
+    }
+
+    // Raw device value of a JSON value in engineering units.
+    pub fn raw_value(&self, json: &serde_json::Value) -> XResult<Value> {
+        let Some(scaling) = &self.scaling else {
+            return self.dtype.coerce(json);
+        };
+
+        let eng = json
+            .as_f64()
+            .ok_or_else(|| XError::new(XErrorKind::TagError, &format!("{json} is not a number")))?;
+
+        self.dtype.cast(scaling.unscale(eng))
+    }
+}
+
+// Report-by-exception settings of a tag. A value change is published only when
+// it exceeds every deadband set and the minimum interval has passed since the
+// last publish; the heartbeat republishes an unchanged value. Quality changes
+// are always published. Intervals are in milliseconds.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct Filter {
+    pub deadband: Option<f64>,
+    pub deadband_percent: Option<f64>,
+    pub min_interval: Option<u64>,
+    pub heartbeat: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Quality {
+    Good,
+    Uncertain,
+    Bad,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum QualityReason {
+    WaitingForInitialData,
+    CommFailure,
+    DeviceException,
+    ConfigError,
+    Stale,
+    Disabled,
+}
+
+// Runtime value of a tag. The source time is when the device produced the
+// value, the update time when the record last changed; a bad reading keeps the
+// last good value and its source time.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TagValue {
+    pub value: Value,
+    pub quality: Quality,
+    pub reason: Option<QualityReason>,
+    pub source_time: Option<DateTime<Utc>>,
+    pub update_time: Option<DateTime<Utc>>,
+}
+
+impl TagValue {
+    pub fn good(value: Value, source_time: DateTime<Utc>) -> Self {
+        TagValue {
+            value,
+            quality: Quality::Good,
+            reason: None,
+            source_time: Some(source_time),
+            update_time: Some(source_time),
+        }
+    }
+
+    pub fn bad(value: Value, reason: QualityReason) -> Self {
+        TagValue {
+            value,
+            quality: Quality::Bad,
+            reason: Some(reason),
+            source_time: None,
+            update_time: Some(Utc::now()),
+        }
+    }
+
+    // Record of a tag that has not been read yet.
+    pub fn initial(tag: &Tag) -> Self {
+        let value = tag.scaled(&tag.value);
+        if tag.address.is_some() || tag.expression.is_some() {
+            TagValue {
+                update_time: None,
+                ..Self::bad(value, QualityReason::WaitingForInitialData)
+            }
+        } else {
+            TagValue {
+                source_time: None,
+                ..Self::good(value, Utc::now())
+            }
+        }
+    }
+}
+
+impl Filter {
+    pub fn is_valid(&self) -> bool {
+        [self.deadband, self.deadband_percent]
+            .iter()
+            .flatten()
+            .all(|v| v.is_finite() && *v >= 0.0)
+    }
+
+    // Whether `value` is worth publishing, given the record last published and
+    // when.
+    pub fn passes(
+        &self,
+        last: Option<&(TagValue, Instant)>,
+        value: &TagValue,
+        now: Instant,
+    ) -> bool {
+        let Some((last, published)) = last else {
+            return true;
+        };
+        let elapsed = now.duration_since(*published);
+
+        if value.quality != last.quality || value.reason != last.reason {
+            return true;
+        }
+        if self
+            .heartbeat
+            .is_some_and(|ms| elapsed >= Duration::from_millis(ms))
+        {
+            return true;
+        }
+        if value.value == last.value {
+            return false;
+        }
+        if self
+            .min_interval
+            .is_some_and(|ms| elapsed < Duration::from_millis(ms))
+        {
+            return false;
+        }
+
+        match (value.value.as_f64(), last.value.as_f64()) {
+            (Some(new), Some(old)) => {
+                let delta = (new - old).abs();
+                self.deadband.is_none_or(|band| delta > band)
+                    && self
+                        .deadband_percent
+                        .is_none_or(|percent| delta > old.abs() * percent / 100.0)
+            }
+            // deadbands only apply to numbers
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagInfo {
+    pub name: String,
+    pub dtype: DataType,
+    pub address: Option<String>,
+    pub description: Option<String>,
+    pub filter: Filter,
+    pub scaling: Option<Scaling>,
+    pub unit: Option<String>,
+    pub expression: Option<String>,
+    pub retention: Option<u64>,
+    #[serde(flatten)]
+    pub value: TagValue,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use chrono::Utc;
+
+    use serde_json::json;
+
+    use super::{Filter, QualityReason, Scaling, Tag, TagValue};
+    use crate::module::value::{DataType, Value};
+
+    #[test]
+    fn filter_changes() {
+        let filter = Filter {
+            deadband: Some(1.0),
+            deadband_percent: Some(5.0),
+            min_interval: Some(100),
+            heartbeat: Some(1000),
+        };
+        let now = Instant::now();
+        let good = |v: f32| TagValue::good(Value::FLOAT(v), Utc::now());
+        let last = (good(100.0), now);
+        let at = |ms: u64| now + Duration::from_millis(ms);
+
+        assert!(filter.passes(None, &good(100.0), now));
+        // inside the percentage deadband
+        assert!(!filter.passes(Some(&last), &good(104.0), at(200)));
+        assert!(filter.passes(Some(&last), &good(106.0), at(200)));
+        // too soon after the last publish
+        assert!(!filter.passes(Some(&last), &good(106.0), at(50)));
+        // quality changes go through at once
+        let bad = TagValue::bad(Value::FLOAT(100.0), QualityReason::CommFailure);
+        assert!(filter.passes(Some(&last), &bad, at(50)));
+        // heartbeat
+        assert!(!filter.passes(Some(&last), &good(100.0), at(999)));
+        assert!(filter.passes(Some(&last), &good(100.0), at(1000)));
+    }
+
+    #[test]
+    fn scaling() {
+        let tag = Tag {
+            name: "level".to_string(),
+            value: Value::INT16(0),
+            dtype: DataType::INT,
+            address: Some("1.41".to_string()),
+            description: None,
+            filter: Filter::default(),
+            scaling: Some(Scaling::Range {
+                raw_low: 0.0,
+                raw_high: 1000.0,
+                eng_low: -50.0,
+                eng_high: 50.0,
+            }),
+            unit: Some("degC".to_string()),
+            expression: None,
+            retention: None,
+        };
+
+        assert_eq!(tag.scaled(&Value::INT16(250)), Value::DOUBLE(-25.0));
+        assert_eq!(tag.raw_value(&json!(-25)).unwrap(), Value::INT16(250));
+        assert_eq!(tag.raw_value(&json!(-50.04)).unwrap(), Value::INT16(0));
+        // beyond INT16 once unscaled
+        assert!(tag.raw_value(&json!(5000)).is_err());
+
+        let linear = Scaling::Linear {
+            k: 0.1,
+            offset: 0.0,
+        };
+        assert!(linear.check(DataType::WORD).is_ok());
+        assert!(linear.check(DataType::BOOL).is_err());
+        assert!(linear.check(DataType::STRING).is_err());
+        assert!(Scaling::Linear {
+            k: 0.0,
+            offset: 1.0
+        }
+        .check(DataType::WORD)
+        .is_err());
+    }
 }
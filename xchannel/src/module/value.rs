@@ -1,5 +1,7 @@
 use serde_derive::{Deserialize, Serialize};
 
+use crate::error::*;
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ValueType {
     BIT,
@@ -44,7 +46,7 @@ pub enum Value {
     },
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
 pub enum DataType {
     BIT,
     BOOL,
@@ -176,4 +178,152 @@ impl Value {
             Value::STRING { length: _, str: _ } => ValueType::STRING,
         }
     }
+
+    // Numeric value, booleans count as 0 and 1.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::BIT(v) => Some(*v as f64),
+            Value::BOOL(v) => Some(u8::from(*v) as f64),
+            Value::UINT8(v) => Some(*v as f64),
+            Value::INT8(v) => Some(*v as f64),
+            Value::UINT16(v) => Some(*v as f64),
+            Value::INT16(v) => Some(*v as f64),
+            Value::UINT32(v) => Some(*v as f64),
+            Value::INT32(v) => Some(*v as f64),
+            Value::FLOAT(v) => Some(*v as f64),
+            Value::UINT64(v) => Some(*v as f64),
+            Value::INT64(v) => Some(*v as f64),
+            Value::DOUBLE(v) => Some(*v),
+            Value::STRING { .. } => None,
+        }
+    }
+}
+
+fn out_of_range(dtype: DataType, json: &serde_json::Value) -> XError {
+    XError::new(
+        XErrorKind::TagError,
+        &format!("{json} is not a valid {dtype:?} value"),
+    )
+}
+
+impl DataType {
+    // Converts a plain JSON value to this type. Integers must be whole and in
+    // range, floats must fit the precision's range, booleans also take 0 and 1.
+    pub fn coerce(&self, json: &serde_json::Value) -> XResult<Value> {
+        let invalid = || out_of_range(*self, json);
+        let integer = || -> XResult<i128> {
+            if let Some(v) = json.as_i64() {
+                Ok(v.into())
+            } else if let Some(v) = json.as_u64() {
+                Ok(v.into())
+            } else {
+                Err(invalid())
+            }
+        };
+        let number = || json.as_f64().ok_or_else(invalid);
+
+        let value = match ValueType::from(*self) {
+            ValueType::BIT => Value::BIT(match json.as_bool() {
+                Some(v) => v.into(),
+                None => u8::try_from(integer()?)
+                    .ok()
+                    .filter(|v| *v <= 1)
+                    .ok_or_else(invalid)?,
+            }),
+            ValueType::BOOL => Value::BOOL(match (json.as_bool(), json.as_u64()) {
+                (Some(v), _) => v,
+                (None, Some(v)) if v <= 1 => v == 1,
+                _ => return Err(invalid()),
+            }),
+            ValueType::UINT8 => Value::UINT8(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::INT8 => Value::INT8(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::UINT16 => Value::UINT16(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::INT16 => Value::INT16(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::UINT32 => Value::UINT32(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::INT32 => Value::INT32(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::UINT64 => Value::UINT64(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::INT64 => Value::INT64(integer()?.try_into().map_err(|_| invalid())?),
+            ValueType::FLOAT => {
+                let v = number()?;
+                if v.abs() > f32::MAX as f64 {
+                    return Err(invalid());
+                }
+                Value::FLOAT(v as f32)
+            }
+            ValueType::DOUBLE => Value::DOUBLE(number()?),
+            ValueType::STRING => Value::STRING {
+                length: None,
+                str: Some(json.as_str().ok_or_else(invalid)?.to_string()),
+            },
+        };
+
+        Ok(value)
+    }
+
+    // Converts a computed number to this type, rounded to a whole number for
+    // integer types; booleans are true unless zero.
+    pub fn cast(&self, v: f64) -> XResult<Value> {
+        let invalid = || {
+            XError::new(
+                XErrorKind::TagError,
+                &format!("{v} is not a {self:?} value"),
+            )
+        };
+        if !v.is_finite() {
+            return Err(invalid());
+        }
+
+        let json = match ValueType::from(*self) {
+            ValueType::BIT | ValueType::BOOL => serde_json::Value::Bool(v != 0.0),
+            ValueType::STRING => serde_json::Value::String(v.to_string()),
+            ValueType::FLOAT | ValueType::DOUBLE => serde_json::json!(v),
+            _ => {
+                let v = v.round();
+                if v < i64::MIN as f64 || v >= u64::MAX as f64 {
+                    return Err(invalid());
+                } else if v < 0.0 {
+                    (v as i64).into()
+                } else {
+                    (v as u64).into()
+                }
+            }
+        };
+
+        self.coerce(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{DataType, Value};
+
+    #[test]
+    fn coerce_json() {
+        assert_eq!(
+            DataType::WORD.coerce(&json!(65535)).unwrap(),
+            Value::UINT16(65535)
+        );
+        assert_eq!(
+            DataType::SINT.coerce(&json!(-128)).unwrap(),
+            Value::INT8(-128)
+        );
+        assert_eq!(DataType::BOOL.coerce(&json!(1)).unwrap(), Value::BOOL(true));
+        assert_eq!(
+            DataType::Real.coerce(&json!(1.5)).unwrap(),
+            Value::FLOAT(1.5)
+        );
+        assert_eq!(
+            DataType::ULINT.coerce(&json!(u64::MAX)).unwrap(),
+            Value::UINT64(u64::MAX)
+        );
+
+        assert!(DataType::WORD.coerce(&json!(65536)).is_err());
+        assert!(DataType::UINT.coerce(&json!(-1)).is_err());
+        assert!(DataType::INT.coerce(&json!(1.5)).is_err());
+        assert!(DataType::BIT.coerce(&json!(2)).is_err());
+        assert!(DataType::FLOAT.coerce(&json!(1e39)).is_err());
+        assert!(DataType::STRING.coerce(&json!(1)).is_err());
+    }
 }
@@ -1,12 +1,20 @@
 use std::sync::Arc;
 
-use warp::{http::StatusCode, Rejection, Reply};
+use futures::{stream, StreamExt};
+use log::warn;
+use tokio::sync::broadcast::error::RecvError;
+use warp::{http::StatusCode, sse::Event, Rejection, Reply};
 
 use crate::error::*;
+use crate::module::alarm::AlarmDef;
 use crate::module::device_manager::DeviceMgr;
+use crate::module::event::Pattern;
 use crate::module::tag::Tag;
 
-use super::request::{AddDevice, AddTable, AddTag, DelTag};
+use super::request::{
+    AddDevice, AddTable, AddTag, AlarmHistory, DelTag, HistoryQuery, UpdateDevice, UpdateTable,
+    UpdateTag, WriteTag, WriteValue,
+};
 use super::response::{DelDevice, DelTable, ErrorResponse, Response};
 
 pub async fn get_drivers(device_mgr: Arc<DeviceMgr>) -> Result<impl Reply, Rejection> {
@@ -35,6 +43,40 @@ pub async fn del_device(name: String, device_mgr: Arc<DeviceMgr>) -> Result<impl
     }
 }
 
+pub async fn update_device(
+    name: String,
+    device: UpdateDevice,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    if let Some(setting) = &device.setting {
+        device_mgr.set_setting(&name, setting).await?;
+    }
+    if let Some(new_name) = &device.name {
+        device_mgr.rename_device(&name, new_name).await?;
+    }
+
+    Ok(ErrorResponse::success())
+}
+
+pub async fn device_status(
+    name: String,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    let status = device_mgr.device_status(&name).await?;
+
+    Ok(Response::with_status(&status, StatusCode::OK))
+}
+
+pub async fn enable_device(
+    name: String,
+    enabled: bool,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    device_mgr.set_enabled(&name, enabled).await?;
+
+    Ok(ErrorResponse::success())
+}
+
 pub async fn add_device(
     device: AddDevice,
     device_mgr: Arc<DeviceMgr>,
@@ -60,7 +102,26 @@ pub async fn add_table(
     device_mgr: Arc<DeviceMgr>,
 ) -> Result<impl Reply, Rejection> {
     device_mgr
-        .add_table(&device, &table.name, table.description, &table.parameter)
+        .add_table(
+            &device,
+            &table.name,
+            table.description,
+            &table.parameter,
+            table.retention,
+        )
+        .await?;
+
+    Ok(ErrorResponse::success())
+}
+
+pub async fn update_table(
+    device: String,
+    table: String,
+    update: UpdateTable,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    device_mgr
+        .update_table(&device, &table, |info| update.apply(info))
         .await?;
 
     Ok(ErrorResponse::success())
@@ -126,6 +187,20 @@ pub async fn del_tags(
     Ok(ErrorResponse::success())
 }
 
+pub async fn update_tag(
+    device: String,
+    table: String,
+    name: String,
+    update: UpdateTag,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    device_mgr
+        .update_tag(&device, &table, &name, |tag| update.apply(tag))
+        .await?;
+
+    Ok(ErrorResponse::success())
+}
+
 pub async fn get_tags(
     query: (String, String, Option<String>),
     device_mgr: Arc<DeviceMgr>,
@@ -134,3 +209,141 @@ pub async fn get_tags(
 
     Ok(Response::with_status(&tags, StatusCode::OK))
 }
+
+pub async fn write_tag(
+    device: String,
+    table: String,
+    name: String,
+    tag: WriteTag,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    device_mgr
+        .write_tag(&device, &table, &name, &tag.value)
+        .await?;
+
+    Ok(ErrorResponse::success())
+}
+
+pub async fn get_values(
+    query: (String, String, Vec<String>),
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    let values = device_mgr.get_values(&query.0, &query.1, &query.2).await?;
+
+    Ok(Response::with_status(&values, StatusCode::OK))
+}
+
+pub async fn write_values(
+    device: String,
+    table: String,
+    values: Vec<WriteValue>,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    let values: Vec<(String, serde_json::Value)> = values
+        .into_iter()
+        .map(|value| (value.name, value.value))
+        .collect();
+    let results = device_mgr.write_tags(&device, &table, &values).await?;
+
+    Ok(Response::results(&results))
+}
+
+// Server-sent events of the tags matching the patterns: the current values
+// first, then every change.
+pub async fn subscribe(
+    patterns: Vec<Pattern>,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    // subscribed before the snapshot so no change falls in between
+    let receiver = device_mgr.subscribe();
+    let snapshot = device_mgr.snapshot(&patterns).await;
+
+    let changes = stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(RecvError::Lagged(n)) => warn!("subscriber lagged, {n} events dropped"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .filter(move |event| {
+        let matched = patterns
+            .iter()
+            .any(|p| p.matches(&event.device, &event.table, &event.name));
+        async move { matched }
+    });
+
+    let events = stream::iter(snapshot)
+        .chain(changes)
+        .map(|event| Event::default().event("tag").json_data(event));
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+pub async fn get_alarms(active: bool, device_mgr: Arc<DeviceMgr>) -> Result<impl Reply, Rejection> {
+    let alarms = device_mgr.get_alarms(active);
+
+    Ok(Response::with_status(&alarms, StatusCode::OK))
+}
+
+pub async fn add_alarm(
+    alarm: AlarmDef,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    device_mgr.add_alarm(alarm).await?;
+
+    Ok(ErrorResponse::success())
+}
+
+pub async fn del_alarm(name: String, device_mgr: Arc<DeviceMgr>) -> Result<impl Reply, Rejection> {
+    if device_mgr.del_alarm(&name).await?.is_some() {
+        Ok(ErrorResponse::success())
+    } else {
+        Ok(ErrorResponse::error(
+            &XError::AlarmError(format!("{name} not found")),
+            StatusCode::NOT_FOUND,
+        ))
+    }
+}
+
+pub async fn ack_alarm(name: String, device_mgr: Arc<DeviceMgr>) -> Result<impl Reply, Rejection> {
+    device_mgr.ack_alarm(&name).await?;
+
+    Ok(ErrorResponse::success())
+}
+
+pub async fn alarm_history(
+    query: AlarmHistory,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    let history = device_mgr
+        .alarm_history(query.name.as_deref(), query.from, query.to, query.limit)
+        .await?;
+
+    Ok(Response::with_status(&history, StatusCode::OK))
+}
+
+pub async fn history(
+    query: HistoryQuery,
+    device_mgr: Arc<DeviceMgr>,
+) -> Result<impl Reply, Rejection> {
+    let tags: Vec<String> = query
+        .tags
+        .split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_string())
+        .collect();
+    let history = device_mgr
+        .history(
+            &query.device,
+            &query.table,
+            &tags,
+            (query.from, query.to),
+            query.aggregate,
+            query.bucket,
+        )
+        .await?;
+
+    Ok(Response::with_status(&history, StatusCode::OK))
+}
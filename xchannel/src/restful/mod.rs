@@ -5,6 +5,7 @@ use std::sync::Arc;
 use warp::{http::Uri, Filter};
 
 use crate::module::device_manager::DeviceMgr;
+use crate::module::event::Pattern;
 
 mod handler;
 mod rejection;
@@ -71,6 +72,28 @@ impl REST {
             .and(Self::with_device_mgr(device_mgr.clone()))
             .and_then(handler::del_device);
 
+        let update_device = warp::put()
+            .and(warp::path!("api" / "v1" / "device" / String))
+            .and(warp::body::json())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::update_device);
+
+        let device_status = warp::get()
+            .and(warp::path!("api" / "v1" / "device" / String / "status"))
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::device_status);
+
+        let enable_device = warp::put()
+            .and(warp::path!("api" / "v1" / "device" / String / "enable"))
+            .map(|name| (name, true))
+            .or(warp::put()
+                .and(warp::path!("api" / "v1" / "device" / String / "disable"))
+                .map(|name| (name, false)))
+            .unify()
+            .untuple_one()
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::enable_device);
+
         let add_tag_table = warp::post()
             .and(warp::path!("api" / "v1" / String / "table"))
             .and(warp::body::json())
@@ -82,6 +105,12 @@ impl REST {
             .and(Self::with_device_mgr(device_mgr.clone()))
             .and_then(handler::del_table);
 
+        let update_tag_table = warp::patch()
+            .and(warp::path!("api" / "v1" / String / "table" / String))
+            .and(warp::body::json())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::update_table);
+
         let get_tag_tables = warp::get()
             .and(warp::path!("api" / "v1" / String / "table"))
             .and(warp::query::<HashMap<String, String>>())
@@ -112,18 +141,113 @@ impl REST {
             .and(Self::with_device_mgr(device_mgr.clone()))
             .and_then(handler::get_tags);
 
+        let update_tag = warp::patch()
+            .and(warp::path!("api" / "v1" / String / String / "tag" / String))
+            .and(warp::body::json())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::update_tag);
+
+        let write_tag = warp::put()
+            .and(warp::path!("api" / "v1" / String / String / "tag" / String))
+            .and(warp::body::json())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::write_tag);
+
+        let get_values = warp::get()
+            .and(warp::path!("api" / "v1" / String / String / "value"))
+            .and(warp::query::<HashMap<String, String>>())
+            .map(|device, table, query: HashMap<String, String>| {
+                let names = query
+                    .get("name")
+                    .map(|x| x.split(',').map(|name| name.to_string()).collect())
+                    .unwrap_or_default();
+                (device, table, names)
+            })
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::get_values);
+
+        let write_values = warp::put()
+            .and(warp::path!("api" / "v1" / String / String / "value"))
+            .and(warp::body::json())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::write_values);
+
+        let subscribe = warp::get()
+            .and(warp::path!("api" / "v1" / "event"))
+            .and(warp::query::<HashMap<String, String>>())
+            .map(|query: HashMap<String, String>| {
+                query
+                    .get("subscribe")
+                    .map_or_else(|| "*".to_string(), |x| x.to_string())
+                    .split(',')
+                    .map(Pattern::from)
+                    .collect::<Vec<Pattern>>()
+            })
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::subscribe);
+
+        let get_alarms = warp::get()
+            .and(warp::path!("api" / "v1" / "alarm"))
+            .and(warp::query::<HashMap<String, String>>())
+            .map(|query: HashMap<String, String>| query.get("active").is_some_and(|x| x == "true"))
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::get_alarms);
+
+        let add_alarm = warp::post()
+            .and(warp::path!("api" / "v1" / "alarm"))
+            .and(warp::body::json())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::add_alarm);
+
+        let del_alarm = warp::delete()
+            .and(warp::path!("api" / "v1" / "alarm" / String))
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::del_alarm);
+
+        let ack_alarm = warp::put()
+            .and(warp::path!("api" / "v1" / "alarm" / String / "ack"))
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::ack_alarm);
+
+        let alarm_history = warp::get()
+            .and(warp::path!("api" / "v1" / "alarm" / "history"))
+            .and(warp::query())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::alarm_history);
+
+        let history = warp::get()
+            .and(warp::path!("api" / "v1" / "history"))
+            .and(warp::query())
+            .and(Self::with_device_mgr(device_mgr.clone()))
+            .and_then(handler::history);
+
         let routes = redirect_dashboard
             .or(dashboard)
             .or(get_drivers)
             .or(get_devices)
             .or(add_device)
             .or(del_device)
+            .or(update_device)
+            .or(device_status)
+            .or(enable_device)
             .or(add_tag_table)
             .or(del_tag_table)
+            .or(update_tag_table)
             .or(get_tag_tables)
             .or(add_tags)
             .or(del_tags)
             .or(get_tags)
+            .or(update_tag)
+            .or(write_tag)
+            .or(get_values)
+            .or(write_values)
+            .or(subscribe)
+            .or(get_alarms)
+            .or(add_alarm)
+            .or(del_alarm)
+            .or(ack_alarm)
+            .or(alarm_history)
+            .or(history)
             .recover(rejection::handle_rejection);
         warp::serve(routes).run(self.host).await;
     }
@@ -1,10 +1,12 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 use crate::error::*;
 
 use crate::module::{
     driver::{Parameter, Setting},
-    tag::Tag,
+    historian::Aggregate,
+    table::TableInfo,
+    tag::{Filter, Scaling, Tag},
     value::*,
 };
 
@@ -15,11 +17,18 @@ pub struct AddDevice {
     pub setting: Option<Setting>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateDevice {
+    pub name: Option<String>,
+    pub setting: Option<Setting>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AddTable {
     pub name: String,
     pub parameter: Parameter,
     pub description: Option<String>,
+    pub retention: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -29,6 +38,53 @@ pub struct AddTag {
     pub dtype: DataType,
     pub address: Option<String>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub filter: Filter,
+    pub scaling: Option<Scaling>,
+    pub unit: Option<String>,
+    pub expression: Option<String>,
+    pub retention: Option<u64>,
+}
+
+// Tells a field set to null, `Some(None)`, from a missing one, `None`.
+fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+// Fields to change; those left out keep their value and null clears optional
+// ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateTable {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub description: Option<Option<String>>,
+    pub parameter: Option<Parameter>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub retention: Option<Option<u64>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateTag {
+    pub name: Option<String>,
+    pub value: Option<Value>,
+    pub dtype: Option<DataType>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub address: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub description: Option<Option<String>>,
+    pub filter: Option<Filter>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub scaling: Option<Option<Scaling>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub unit: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub expression: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub retention: Option<Option<u64>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,24 +92,108 @@ pub struct DelTag {
     pub name: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct WriteTag {
+    pub value: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WriteValue {
+    pub name: String,
+    pub value: serde_json::Value,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+// Time range in milliseconds since the epoch.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlarmHistory {
+    pub name: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_aggregate() -> Aggregate {
+    Aggregate::Raw
+}
+
+// Recorded values of comma separated `tags` of a table, in milliseconds since
+// the epoch. Aggregates other than raw need a bucket size in milliseconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryQuery {
+    pub device: String,
+    pub table: String,
+    pub tags: String,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    #[serde(default = "default_aggregate")]
+    pub aggregate: Aggregate,
+    pub bucket: Option<i64>,
+}
+
+impl UpdateTable {
+    pub fn apply(&self, table: TableInfo) -> TableInfo {
+        TableInfo {
+            name: self.name.clone().unwrap_or(table.name),
+            description: self.description.clone().unwrap_or(table.description),
+            parameter: self.parameter.clone().unwrap_or(table.parameter),
+            retention: self.retention.unwrap_or(table.retention),
+        }
+    }
+}
+
+impl UpdateTag {
+    // The tag with the changes, checked as a new one would be. A new type
+    // without a value starts from its default.
+    pub fn apply(&self, tag: Tag) -> XResult<Tag> {
+        let value = match (&self.value, self.dtype) {
+            (Some(value), _) => value.clone(),
+            (None, Some(dtype)) if dtype != tag.dtype => ValueType::from(dtype).default_value(),
+            (None, _) => tag.value,
+        };
+
+        let tag = Tag {
+            name: self.name.clone().unwrap_or(tag.name),
+            value,
+            dtype: self.dtype.unwrap_or(tag.dtype),
+            address: self.address.clone().unwrap_or(tag.address),
+            description: self.description.clone().unwrap_or(tag.description),
+            filter: self.filter.unwrap_or(tag.filter),
+            scaling: self.scaling.unwrap_or(tag.scaling),
+            unit: self.unit.clone().unwrap_or(tag.unit),
+            expression: self.expression.clone().unwrap_or(tag.expression),
+            retention: self.retention.unwrap_or(tag.retention),
+        };
+        tag.check()?;
+
+        Ok(tag)
+    }
+}
+
 impl TryFrom<&AddTag> for Tag {
     type Error = XError;
 
     fn try_from(tag: &AddTag) -> XResult<Self> {
         let vtype: ValueType = tag.dtype.into();
 
-        if let Some(v) = &tag.value {
-            if vtype != v.v_type() {
-                return Err(XError::new(XErrorKind::TagError, "Tag value type mismatch"));
-            }
-        }
-
-        Ok(Tag {
+        let tag = Tag {
             name: tag.name.clone(),
             value: tag.value.clone().unwrap_or(vtype.default_value()),
             dtype: tag.dtype,
             address: tag.address.clone(),
             description: tag.description.clone(),
-        })
+            filter: tag.filter,
+            scaling: tag.scaling,
+            unit: tag.unit.clone(),
+            expression: tag.expression.clone(),
+            retention: tag.retention,
+        };
+        tag.check()?;
+
+        Ok(tag)
     }
 }
@@ -5,7 +5,7 @@ use warp::{
     reply::{Json, WithStatus},
 };
 
-use crate::error::XError;
+use crate::error::{XError, XResult};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ErrorResponse<'a> {
@@ -54,6 +54,29 @@ impl Response {
         reply::with_status(reply::json(&ResponseMsg { index, message: msg }), StatusCode::PARTIAL_CONTENT)
     }
 
+    // One indexed message per item; partial content when any of them failed.
+    pub fn results(results: &[XResult<()>]) -> WithStatus<Json> {
+        let messages: Vec<String> = results
+            .iter()
+            .map(|result| match result {
+                Ok(()) => "success".to_string(),
+                Err(e) => e.to_string(),
+            })
+            .collect();
+        let body: Vec<ResponseMsg> = messages
+            .iter()
+            .enumerate()
+            .map(|(index, message)| ResponseMsg { index: index as i32, message })
+            .collect();
+        let status = if results.iter().all(|result| result.is_ok()) {
+            StatusCode::OK
+        } else {
+            StatusCode::PARTIAL_CONTENT
+        };
+
+        reply::with_status(reply::json(&body), status)
+    }
+
     pub fn response(message: &str, status: StatusCode) -> WithStatus<Json> {
         reply::with_status(reply::json(&ResponseMsg { index: 0, message }), status)
     }